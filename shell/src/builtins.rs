@@ -0,0 +1,195 @@
+//! Commands that the shell runs itself, instead of spawning a process. They
+//! change the shell's own state (its directory, environment, or whether it's
+//! running at all), which a child process can't do for it.
+use std::env::{self, current_dir, set_current_dir, split_paths, var, var_os};
+use std::fs;
+use std::io::{stdout, Error, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// A builtin, taking its argv and where to write its output. Its error is
+/// reported on its stderr, after its name.
+pub type Builtin = fn(&[String], &mut dyn Write) -> Result<(), Error>;
+
+/// Every builtin, by name. A command is looked up here before it's spawned.
+const BUILTINS: &[(&str, Builtin)] = &[
+    ("cd", do_cd),
+    ("exit", do_exit),
+    ("export", do_export),
+    ("unset", do_unset),
+    ("pwd", do_pwd),
+    ("which", do_which),
+];
+
+/// Find the builtin called `name`, if there is one
+pub fn lookup_builtin(name: &str) -> Option<Builtin> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, builtin)| *builtin)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// `cd [dir]`: change to `dir`, or to $HOME. `cd -` goes back to the previous
+/// directory and prints it.
+fn do_cd(argv: &[String], out: &mut dyn Write) -> Result<(), Error> {
+    let back = argv.get(1).map(String::as_str) == Some("-");
+    let dir = match argv.get(1).map(String::as_str) {
+        None => var("HOME").map_err(|_| invalid(String::from("HOME not set")))?,
+        Some("-") => var("OLDPWD").map_err(|_| invalid(String::from("OLDPWD not set")))?,
+        Some(dir) => dir.to_string(),
+    };
+    if argv.len() > 2 {
+        return Err(invalid(String::from("too many arguments")));
+    }
+
+    let old = current_dir().ok();
+    set_current_dir(&dir).map_err(|e| Error::new(e.kind(), format!("{}: {}", dir, e)))?;
+    if let Some(old) = old {
+        env::set_var("OLDPWD", old);
+    }
+    if let Ok(new) = current_dir() {
+        env::set_var("PWD", new);
+    }
+
+    // Only once we're there, like bash
+    if back {
+        writeln!(out, "{}", dir)?;
+    }
+    Ok(())
+}
+
+/// `exit [status]`: leave the shell, with `status` or else 0
+fn do_exit(argv: &[String], _out: &mut dyn Write) -> Result<(), Error> {
+    let status = match argv.get(1) {
+        None => 0,
+        Some(status) => status
+            .parse::<i32>()
+            .map_err(|_| invalid(format!("{}: numeric argument required", status)))?,
+    };
+    if argv.len() > 2 {
+        return Err(invalid(String::from("too many arguments")));
+    }
+
+    let _ = stdout().flush();
+    std::process::exit(status);
+}
+
+/// Whether `name` can be the name of an environment variable
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+fn not_an_identifier(name: &str) -> Error {
+    invalid(format!("`{}': not a valid identifier", name))
+}
+
+/// `export [name[=value]...]`: set variables in the environment that every
+/// command gets. With no arguments, list the environment.
+fn do_export(argv: &[String], out: &mut dyn Write) -> Result<(), Error> {
+    if argv.len() == 1 {
+        let mut vars: Vec<_> = env::vars_os().collect();
+        vars.sort();
+        for (name, value) in vars {
+            writeln!(
+                out,
+                "export {}={:?}",
+                name.to_string_lossy(),
+                value.to_string_lossy()
+            )?;
+        }
+        return Ok(());
+    }
+
+    // Every variable is already exported, so a name on its own does nothing
+    for arg in &argv[1..] {
+        let (name, value) = match arg.find('=') {
+            Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+            None => (&arg[..], None),
+        };
+        if !is_valid_name(name) {
+            return Err(not_an_identifier(arg));
+        }
+        if let Some(value) = value {
+            if value.contains('\0') {
+                return Err(invalid(format!("{}: value contains a null byte", name)));
+            }
+            env::set_var(name, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// `unset name...`: remove variables from the environment
+fn do_unset(argv: &[String], _out: &mut dyn Write) -> Result<(), Error> {
+    for name in &argv[1..] {
+        if !is_valid_name(name) {
+            return Err(not_an_identifier(name));
+        }
+        env::remove_var(name);
+    }
+
+    Ok(())
+}
+
+/// `pwd`: print the current directory
+fn do_pwd(_argv: &[String], out: &mut dyn Write) -> Result<(), Error> {
+    writeln!(out, "{}", current_dir()?.display())
+}
+
+/// Whether `path` is a file that we could run
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Where `name` would be found when it's run: itself if it has a `/` in it,
+/// or else the first directory of $PATH that has it
+fn find_program(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if is_executable(&path) {
+            Some(path)
+        } else {
+            None
+        };
+    }
+    let path = var_os("PATH")?;
+    split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+/// `which name...`: print what each command would run, whether that's a
+/// builtin or a program
+fn do_which(argv: &[String], out: &mut dyn Write) -> Result<(), Error> {
+    let mut missing = Vec::new();
+    for name in &argv[1..] {
+        if lookup_builtin(name).is_some() {
+            writeln!(out, "{}: shell builtin", name)?;
+        } else if let Some(path) = find_program(name) {
+            writeln!(out, "{}", path.display())?;
+        } else {
+            missing.push(&name[..]);
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        let message = format!("no {} in $PATH", missing.join(", "));
+        Err(Error::new(ErrorKind::NotFound, message))
+    }
+}
@@ -1,156 +1,348 @@
+pub mod builtins;
 pub mod parser;
-use crate::parser::parse;
-use execute::command;
-use execute::Execute;
+use crate::builtins::{lookup_builtin, Builtin};
+use crate::parser::{parse, Pipeline, Redirect, RedirectKind, SimpleCommand};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, getuid, pipe2, ForkResult, Pid, User};
 use std::env::{current_dir, var};
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::{stderr, stdin, stdout, Error, Write};
-use std::process::{id, Command, Stdio};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{id, Child, Command, ExitStatus, Stdio};
 
 fn main() {
-    while let Ok(tokens) = tokenize_input() {
-        if let Some(process) = setup_process(&tokens) {
-            if let Err(e) = execute(process) {
-                eprintln!("Error executing process:\n{}", e);
+    loop {
+        let line = match read_input() {
+            Ok(Some(line)) => line,
+            Ok(None) => break, // End of input, e.g. ^D at the prompt
+            Err(e) => {
+                eprintln!("Error reading input:\n{}", e);
+                break;
             }
+        };
+        match parse(&line) {
+            Ok(pipeline) => {
+                if let Err(e) = execute(&pipeline) {
+                    eprintln!("Error executing process:\n{}", e);
+                }
+            }
+            Err(e) => eprintln!("Could not parse the command:\n{}", e),
         }
     }
 }
 
-fn execute(mut process: Command) -> Result<(), Error> {
-    match process.spawn() {
-        Ok(child) => {
-            let pid = child.id();
-            println!("Process {} is executing", pid);
+/// Run every command of a pipeline at once, each one's stdout connected to the
+/// next one's stdin, and wait for all of them. Their output goes straight to
+/// wherever it's sent, and the status of the last command is reported as the
+/// pipeline's.
+fn execute(pipeline: &Pipeline) -> Result<(), Error> {
+    // A builtin on its own runs right here in the shell, which is what lets
+    // `cd` and `exit` work
+    if let [command] = &pipeline.commands[..] {
+        if let Some(builtin) = lookup_builtin(&command.argv[0]) {
+            run_builtin(builtin, command, Streams::default());
+            return Ok(());
+        }
+    }
 
-            let output = child.wait_with_output()?;
+    let mut upstream: Option<File> = None;
+    let mut children: Vec<Process> = Vec::new();
+    let mut last: Option<Process> = None;
 
-            stdout().write_all(&output.stdout)?;
-            stderr().write_all(&output.stderr)?;
-            println!(
-                "\nProcess {} finished with exit code {}",
-                pid, output.status
-            );
+    for (i, command) in pipeline.commands.iter().enumerate() {
+        // Each command after the first reads the output of the one before it
+        let mut streams = Streams {
+            stdin: upstream.take(),
+            ..Streams::default()
+        };
+        let is_last = i + 1 == pipeline.commands.len();
+        if !is_last {
+            let (read_end, write_end) = match pipe() {
+                Ok(ends) => ends,
+                Err(e) => {
+                    // The rest of the pipeline can't be started, so stop what
+                    // has been, rather than leave it running unreaped
+                    drop(streams);
+                    for mut child in children {
+                        child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            };
+            streams.stdout = Some(write_end);
+            upstream = Some(read_end);
+        }
 
-            Ok(())
+        // A command that can't be started is left out. The pipes around it are
+        // closed with it, so its neighbours see the end of their input, or
+        // nothing reading their output, and finish up.
+        let process = match lookup_builtin(&command.argv[0]) {
+            Some(builtin) => spawn_builtin(builtin, command, streams, &mut upstream),
+            None => setup_process(command, streams)
+                .and_then(|mut process| process.spawn())
+                .map(Process::Program),
+        };
+        match process {
+            Ok(process) => {
+                println!("Process {} is executing", process.id());
+                if is_last {
+                    last = Some(process);
+                } else {
+                    children.push(process);
+                }
+            }
+            Err(e) => eprintln!("Error executing process:\n{}", e),
         }
-        Err(e) => Err(e),
     }
+
+    // Reap every child, so that none of them is left a zombie
+    for mut child in children {
+        let _ = child.wait();
+    }
+    if let Some(mut child) = last {
+        let pid = child.id();
+        let status = child.wait()?;
+        println!("\nProcess {} finished with exit code {}", pid, status);
+    }
+
+    Ok(())
 }
 
-fn tokenize_input() -> Result<Vec<String>, Error> {
-    let curr = current_dir()?;
-    let curr = curr.display();
-    let user = var("USER").unwrap();
-    print!("{}@{} {} > ", user, id(), curr);
-    stdout().flush()?;
-    let mut buffer = String::new();
-    stdin().read_line(&mut buffer)?;
-    let parsed = parse(&buffer);
+/// A running command of a pipeline: a program, or a copy of the shell that's
+/// running a builtin
+enum Process {
+    Program(Child),
+    Builtin(Pid),
+}
+
+impl Process {
+    fn id(&self) -> u32 {
+        match self {
+            Process::Program(child) => child.id(),
+            Process::Builtin(pid) => pid.as_raw() as u32,
+        }
+    }
+
+    fn kill(&mut self) {
+        match self {
+            Process::Program(child) => {
+                let _ = child.kill();
+            }
+            Process::Builtin(pid) => {
+                let _ = kill(*pid, Signal::SIGKILL);
+            }
+        }
+    }
+
+    /// Wait for the process to finish, and reap it
+    fn wait(&mut self) -> Result<ExitStatus, Error> {
+        match self {
+            Process::Program(child) => child.wait(),
+            Process::Builtin(pid) => match waitpid(*pid, None) {
+                Ok(WaitStatus::Exited(_, code)) => Ok(ExitStatus::from_raw(code << 8)),
+                Ok(WaitStatus::Signaled(_, signal, _)) => Ok(ExitStatus::from_raw(signal as i32)),
+                Ok(status) => Err(Error::other(format!("unexpected wait status {:?}", status))),
+                Err(_) => Err(Error::last_os_error()),
+            },
+        }
+    }
+}
 
-    Ok(parsed)
+/// Run a builtin that's one command of a longer pipeline. Like bash, it runs
+/// in a copy of the shell, so `cd dir | cat` leaves the shell where it was and
+/// `exit | cat` doesn't end it. The copy closes `upstream`, the shell's end of
+/// the pipe that the builtin writes to, so that the builtin sees it if nothing
+/// is reading its output any more.
+fn spawn_builtin(
+    builtin: Builtin,
+    command: &SimpleCommand,
+    streams: Streams,
+    upstream: &mut Option<File>,
+) -> Result<Process, Error> {
+    // Anything still buffered would be written twice
+    stdout().flush()?;
+    match fork().map_err(|_| Error::last_os_error())? {
+        ForkResult::Parent { child } => Ok(Process::Builtin(child)),
+        ForkResult::Child => {
+            drop(upstream.take());
+            let status = run_builtin(builtin, command, streams);
+            let _ = stdout().flush();
+            std::process::exit(status);
+        }
+    }
 }
 
-fn setup_process(tokens: &[String]) -> Option<Command> {
-    let commands = tokens.iter().fold(Vec::new(), |mut acc, token| {
-        // If the token is a redirector or the accumulator is empty,
-        // create a new vector in the accumulator
-        if is_redirect(&token) || acc.is_empty() {
-            acc.push(Vec::new());
+/// Run a builtin in the shell, with its output and errors sent where `streams`
+/// and its own redirections say. Its input is never read, so any pipe into it
+/// is just closed. Returns its exit status: 0, or 1 if it failed.
+fn run_builtin(builtin: Builtin, command: &SimpleCommand, streams: Streams) -> i32 {
+    let streams = match open_streams(streams, &command.redirects) {
+        Ok(streams) => streams,
+        Err(e) => {
+            eprintln!("Error executing process:\n{}", e);
+            return 1;
         }
-        // Obtain the last vector within the accumulator and push the token onto it
-        acc.last_mut().unwrap().push(&token[..]);
+    };
+    let mut out: Box<dyn Write> = match streams.stdout {
+        Some(file) => Box::new(file),
+        None => Box::new(stdout()),
+    };
+    let mut err: Box<dyn Write> = match streams.stderr {
+        Some(file) => Box::new(file),
+        None => Box::new(stderr()),
+    };
 
-        // Return the current accumulator and continue processing the tokens
-        acc
-    });
-    let mut process: Option<Command> = None;
-    let mut iter = commands.iter();
+    let result = builtin(&command.argv, &mut out);
+    let _ = out.flush();
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = writeln!(err, "{}: {}", command.argv[0], e);
+            1
+        }
+    }
+}
 
-    while let Some(command) = iter.next() {
-        process = redirect(&command[..], process).expect("Failed");
+/// Show the prompt and read a line of input. Returns None at the end of the
+/// input.
+fn read_input() -> Result<Option<String>, Error> {
+    // The directory may have been removed from under us
+    let curr = match current_dir() {
+        Ok(curr) => curr.display().to_string(),
+        Err(_) => String::from("?"),
+    };
+    print!("{}@{} {} > ", user_name(), id(), curr);
+    stdout().flush()?;
+    let mut buffer = String::new();
+    if stdin().read_line(&mut buffer)? == 0 {
+        // Finish the prompt's line before leaving
+        println!();
+        return Ok(None);
     }
 
-    process
+    Ok(Some(buffer))
 }
 
-fn is_redirect(token: &str) -> bool {
-    match token {
-        "<" | ">" | ">>" | "<<" | "1>" | "2>" | "|" | "&>" | "1>&2" | "2>&1" => true,
-        _ => false,
+/// The name of the user, from $USER or else the password database
+fn user_name() -> String {
+    if let Ok(user) = var("USER") {
+        return user;
+    }
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => String::from("?"),
     }
 }
 
-fn redirect(command: &[&str], process: Option<Command>) -> Result<Option<Command>, Error> {
-    let redirection = command.first().unwrap();
-    match &redirection[..] {
-        /*
-        // ---- Append redirection ----
-        ">>" => handle_append_redirect(command, process),
+/// Build the process for a command, with its stdio set up as `streams` says,
+/// and then as its own redirections say
+fn setup_process(command: &SimpleCommand, streams: Streams) -> Result<Command, Error> {
+    let mut process = new_process(command);
+    let streams = open_streams(streams, &command.redirects)?;
+    streams.apply(&mut process);
 
-        // ---- stderr redirection ----
-        "2>" => handle_stderr_redirect(command, process),
+    Ok(process)
+}
 
-        // ---- stdout and stderr redirection ----
-        "&>" => handle_stdout_stderr_redirect(command, process),
+fn new_process(command: &SimpleCommand) -> Command {
+    let mut proc: Command = Command::new(&command.argv[0]);
+    proc.args(&command.argv[1..]);
 
-        */
-        // ---- Stdout redirection ----
-        ">" | "1>" => redirect_stdout(&command[1..], process),
+    proc
+}
 
-        // ---- Stdin redirection ----
-        "<" => redirect_stdin(command, process),
-        // ---- pipe in between processes ----
-        "|" => pipe(&command[1..], process),
-        _ => {
-            let mut proc: Command = Command::new(&command[0]);
-            proc.args(&command[1..]);
-
-            Ok(Some(proc))
+/// The files that a process's stdio is redirected to. None means the stream
+/// is left as the shell set it up.
+#[derive(Default)]
+struct Streams {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+}
+
+impl Streams {
+    fn apply(self, process: &mut Command) {
+        if let Some(file) = self.stdin {
+            process.stdin(Stdio::from(file));
+        }
+        if let Some(file) = self.stdout {
+            process.stdout(Stdio::from(file));
+        }
+        if let Some(file) = self.stderr {
+            process.stderr(Stdio::from(file));
         }
     }
 }
 
-fn redirect_stdout(command: &[&str], process: Option<Command>) -> Result<Option<Command>, Error> {
-    match process {
-        Some(mut process) => {
-            let filename = command.first().unwrap_or(&"ERROR_DEFAULT.txt");
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(filename)?;
+/// Open the files of a command's redirections, left to right, so that a later
+/// redirection of a stream replaces an earlier one (or the pipe in `streams`)
+fn open_streams(mut streams: Streams, redirects: &[Redirect]) -> Result<Streams, Error> {
+    for redirection in redirects {
+        redirect(redirection, &mut streams)?;
+    }
 
-            process.stdout(Stdio::from(file));
+    Ok(streams)
+}
+
+fn redirect(redirection: &Redirect, streams: &mut Streams) -> Result<(), Error> {
+    let filename = &redirection.target[..];
+    match redirection.kind {
+        // ---- Stdout redirection ----
+        RedirectKind::Stdout => streams.stdout = Some(create(filename, false)?),
+
+        // ---- Append redirection ----
+        RedirectKind::Append => streams.stdout = Some(create(filename, true)?),
+
+        // ---- stderr redirection ----
+        RedirectKind::Stderr => streams.stderr = Some(create(filename, false)?),
 
-            Ok(Some(process))
+        // ---- stdout and stderr redirection ----
+        RedirectKind::Both => {
+            let file = create(filename, false)?;
+            streams.stderr = Some(file.try_clone()?);
+            streams.stdout = Some(file);
         }
-        None => Ok(None),
+
+        // ---- Stdin redirection ----
+        RedirectKind::Stdin => streams.stdin = Some(OpenOptions::new().read(true).open(filename)?),
+
+        // ---- fd duplication, to wherever the other stream goes right now ----
+        RedirectKind::StderrToStdout => streams.stderr = Some(duplicate(&streams.stdout, 1)?),
+        RedirectKind::StdoutToStderr => streams.stdout = Some(duplicate(&streams.stderr, 2)?),
     }
+
+    Ok(())
 }
-fn redirect_stdin(command: &[&str], process: Option<Command>) -> Result<Option<Command>, Error> {
-    match process {
-        Some(mut process) => {
-            let filename = command.first().unwrap_or(&"ERROR_DEFAULT.txt");
-            let file = OpenOptions::new().read(true).open(filename)?;
 
-            process.stdin(Stdio::from(file));
+/// Open a file to write output to, truncating it unless `append` is set
+fn create(filename: &str, append: bool) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(filename)
+}
 
-            Ok(Some(process))
+/// A copy of a redirected stream, or of the shell's own `fd` if the stream
+/// isn't redirected. Like every file the shell opens, it's closed on exec, so
+/// only the process that it's given to keeps it.
+fn duplicate(stream: &Option<File>, fd: RawFd) -> Result<File, Error> {
+    match stream {
+        Some(file) => file.try_clone(),
+        None => {
+            let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0)).map_err(|_| Error::last_os_error())?;
+            Ok(unsafe { File::from_raw_fd(fd) })
         }
-        None => Ok(None),
     }
 }
-fn pipe(command: &[&str], process: Option<Command>) -> Result<Option<Command>, Error> {
-    if let Some(mut process) = process {
-        process.stdout(Stdio::piped());
-        let child = process.spawn()?;
-        if let Some(output) = child.stdout {
-            let mut new_proc = Command::new(&command[0]);
-            new_proc.args(&command[1..]).stdin(Stdio::from(output));
 
-            return Ok(Some(new_proc));
-        }
-    }
-    Ok(None)
+/// A pipe, as the files of its read and write ends
+fn pipe() -> Result<(File, File), Error> {
+    let (read_end, write_end) = pipe2(OFlag::O_CLOEXEC).map_err(|_| Error::last_os_error())?;
+    Ok(unsafe { (File::from_raw_fd(read_end), File::from_raw_fd(write_end)) })
 }
@@ -1,40 +1,373 @@
 extern crate pest;
 
+use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct CLIParser;
 
-pub fn parse(input: &str) -> Vec<String> {
+/// The byte range of the input line that a piece of the AST came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl<'i> From<pest::Span<'i>> for Span {
+    fn from(span: pest::Span<'i>) -> Self {
+        Span {
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+/// Where a redirection sends (or takes) a process's stdio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    Stdin,          // <
+    Stdout,         // > or 1>
+    Append,         // >>
+    Stderr,         // 2>
+    Both,           // &>
+    StderrToStdout, // 2>&1
+    StdoutToStderr, // 1>&2
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub kind: RedirectKind,
+    pub target: String, // The file, which is empty for 2>&1 and 1>&2
+    pub span: Span,
+}
+
+/// A single program to run, with its arguments and redirections
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub argv: Vec<String>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+/// Commands joined by `|`, each one's stdout feeding the next one's stdin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    pub span: Span,
+}
+
+/// A line that doesn't match the grammar. Its Display shows the line, with the
+/// line and column where parsing failed, and what was expected there.
+pub type ParseError = pest::error::Error<Rule>;
+
+pub fn parse(input: &str) -> Result<Pipeline, ParseError> {
     // We are parsing the input string via the `line` rule in grammar.pest
-    let parsed = CLIParser::parse(Rule::line, input)
-        .expect("Failed to parse")
+    let line = CLIParser::parse(Rule::line, input)
+        .map_err(|e| e.renamed_rules(describe))?
         .next()
         .unwrap();
+    let span = line.as_span().into();
 
-    let mut tokens: Vec<String> = Vec::new();
-    // Gets `line`
-    for line in parsed.into_inner() {
-        // `line` is either `quoted` or `commands`
-        match line.as_rule() {
-            Rule::quoted => {
-                // Trim the quotation marks off of the string
-                let token = line.as_str();
-                let token = String::from(&token[1..token.len() - 1]);
-                tokens.push(token);
-            }
-            Rule::commands => {
-                // Get each `command` present
-                for command in line.into_inner() {
-                    tokens.push(String::from(command.as_str()));
+    // `line` holds a `pipeline`, unless it's blank
+    let pipeline = line.into_inner().find(|x| x.as_rule() == Rule::pipeline);
+    Ok(match pipeline {
+        Some(pipeline) => parse_pipeline(pipeline),
+        None => Pipeline {
+            commands: Vec::new(),
+            span,
+        },
+    })
+}
+
+/// What a rule is called in error messages
+fn describe(rule: &Rule) -> String {
+    let description = match rule {
+        Rule::EOI => "the end of the line",
+        Rule::arg
+        | Rule::plain
+        | Rule::escaped
+        | Rule::single_quoted
+        | Rule::single_text
+        | Rule::double_quoted
+        | Rule::double_text
+        | Rule::double_escaped => "an argument",
+        Rule::redirect | Rule::stdio | Rule::dup => "a redirection",
+        Rule::simple_command => "a command",
+        Rule::pipeline | Rule::line => "a pipeline",
+        Rule::WHITESPACE => "a space",
+        Rule::metachar => "a separator",
+    };
+    String::from(description)
+}
+
+fn parse_pipeline(pipeline: Pair<Rule>) -> Pipeline {
+    let span = pipeline.as_span().into();
+    let commands = pipeline.into_inner().map(parse_command).collect();
+    Pipeline { commands, span }
+}
+
+fn parse_command(command: Pair<Rule>) -> SimpleCommand {
+    let span = command.as_span().into();
+    let mut argv = Vec::new();
+    let mut redirects = Vec::new();
+
+    // Each part of a `simple_command` is a `redirect` or an `arg`
+    for part in command.into_inner() {
+        match part.as_rule() {
+            Rule::redirect => redirects.push(parse_redirect(part)),
+            _ => argv.push(parse_arg(part)),
+        }
+    }
+    SimpleCommand {
+        argv,
+        redirects,
+        span,
+    }
+}
+
+fn parse_redirect(redirect: Pair<Rule>) -> Redirect {
+    let span = redirect.as_span().into();
+    let mut inner = redirect.into_inner();
+    let kind = match inner.next().unwrap().as_str() {
+        "<" => RedirectKind::Stdin,
+        ">>" => RedirectKind::Append,
+        "2>" => RedirectKind::Stderr,
+        "&>" => RedirectKind::Both,
+        "2>&1" => RedirectKind::StderrToStdout,
+        "1>&2" => RedirectKind::StdoutToStderr,
+        _ => RedirectKind::Stdout,
+    };
+    let target = inner.next().map(parse_arg).unwrap_or_default();
+    Redirect { kind, target, span }
+}
+
+/// The text of an argument, with its quotes and escapes taken out
+fn parse_arg(arg: Pair<Rule>) -> String {
+    let mut text = String::new();
+    for part in arg.into_inner() {
+        match part.as_rule() {
+            Rule::plain => text.push_str(part.as_str()),
+            // A backslash before the end of the line just continues it
+            Rule::escaped => text.push_str(part.as_str()[1..].trim_end_matches('\n')),
+            Rule::single_quoted => text.push_str(part.into_inner().next().unwrap().as_str()),
+            Rule::double_quoted => {
+                for piece in part.into_inner() {
+                    match piece.as_rule() {
+                        Rule::double_escaped => text.push_str(&piece.as_str()[1..]),
+                        _ => text.push_str(piece.as_str()),
+                    }
                 }
             }
-            _ => {
-                unreachable!();
-            }
+            _ => unreachable!(),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pest::{consumes_to, fails_with, parses_to};
+
+    /// The argv of each command in `line`
+    fn argvs(line: &str) -> Vec<Vec<String>> {
+        let pipeline = parse(line).unwrap();
+        pipeline.commands.into_iter().map(|c| c.argv).collect()
+    }
+
+    #[test]
+    fn test_plain_words() {
+        let words = [
+            "--opt=val",
+            "a,b",
+            "user@host",
+            ":",
+            "+x",
+            "%s",
+            "~/dir/*.rs",
+            "$HOME",
+            "1",
+            "héllo.txt",
+            "日本語",
+        ];
+        for word in &words {
+            assert_eq!(argvs(&format!("echo {}\n", word)), [["echo", word]]);
         }
     }
 
-    tokens
+    #[test]
+    fn test_metacharacters() {
+        assert_eq!(argvs("a|b"), [["a"], ["b"]]);
+        assert_eq!(argvs("\ta\t b \n"), [["a", "b"]]);
+        let command = &parse("cat<in>out").unwrap().commands[0];
+        assert_eq!(command.argv, ["cat"]);
+        assert_eq!(command.redirects[0].target, "in");
+        assert_eq!(command.redirects[1].target, "out");
+        for line in &["a;b", "a&b", "(a)"] {
+            assert!(parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_quotes() {
+        assert_eq!(argvs("echo 'a b'"), [["echo", "a b"]]);
+        assert_eq!(argvs(r#"echo "a b""#), [["echo", "a b"]]);
+        assert_eq!(argvs(r#"echo '' """#), [["echo", "", ""]]);
+        assert_eq!(argvs("echo '\"' \"'\""), [["echo", "\"", "'"]]);
+        assert_eq!(argvs("echo 'a | b > c'"), [["echo", "a | b > c"]]);
+        assert_eq!(
+            argvs(r#"git commit --message="fix it" -a'm'"#),
+            [["git", "commit", "--message=fix it", "-am"]]
+        );
+        assert_eq!(argvs(r#"echo "q"w'e'"r""#), [["echo", "qwer"]]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            argvs(r"echo a\ b \| \> \\"),
+            [["echo", "a b", "|", ">", "\\"]]
+        );
+        assert_eq!(argvs(r"echo it\'s \x"), [["echo", "it's", "x"]]);
+        assert_eq!(argvs("echo a\\\n"), [["echo", "a"]]);
+        // Only `"` and `\` are escaped between double quotes
+        assert_eq!(argvs(r#"echo "\"\\\n\$""#), [["echo", r#""\\n\$"#]]);
+        // Nothing is escaped between single quotes
+        assert_eq!(argvs(r"echo 'a\'"), [["echo", r"a\"]]);
+    }
+
+    #[test]
+    fn test_pipelines() {
+        assert_eq!(
+            argvs("ls -l | grep x | sort -r | head"),
+            [
+                vec!["ls", "-l"],
+                vec!["grep", "x"],
+                vec!["sort", "-r"],
+                vec!["head"]
+            ]
+        );
+        let pipeline = parse("  a 1 |b|  c").unwrap();
+        let spans: Vec<_> = pipeline.commands.iter().map(|c| c.span).collect();
+        assert_eq!(
+            spans,
+            [
+                Span { start: 2, end: 5 },
+                Span { start: 7, end: 8 },
+                Span { start: 11, end: 12 }
+            ]
+        );
+        assert!(parse("  \n").unwrap().commands.is_empty());
+    }
+
+    #[test]
+    fn test_redirections() {
+        let command = &parse("< in cat >> 'log file' 2>err a 2>&1 b 1>&2 &>all 1>out >o")
+            .unwrap()
+            .commands[0];
+        assert_eq!(command.argv, ["cat", "a", "b"]);
+        let redirects: Vec<_> = command
+            .redirects
+            .iter()
+            .map(|r| (r.kind, &r.target[..]))
+            .collect();
+        assert_eq!(
+            redirects,
+            [
+                (RedirectKind::Stdin, "in"),
+                (RedirectKind::Append, "log file"),
+                (RedirectKind::Stderr, "err"),
+                (RedirectKind::StderrToStdout, ""),
+                (RedirectKind::StdoutToStderr, ""),
+                (RedirectKind::Both, "all"),
+                (RedirectKind::Stdout, "out"),
+                (RedirectKind::Stdout, "o"),
+            ]
+        );
+        assert_eq!(command.redirects[1].span, Span { start: 9, end: 22 });
+
+        // Each stage of a pipeline has its own
+        let pipeline = parse("a <x | b >y").unwrap();
+        assert_eq!(pipeline.commands[0].redirects[0].target, "x");
+        assert_eq!(pipeline.commands[1].redirects[0].kind, RedirectKind::Stdout);
+        assert_eq!(pipeline.commands[1].redirects[0].target, "y");
+    }
+
+    #[test]
+    fn test_rejected_input() {
+        let lines = [
+            "| a",
+            "a |",
+            "a | | b",
+            "a ||",
+            "cat >",
+            "cat < | b",
+            "> out",
+            "2>&1",
+            "a >> >> b",
+        ];
+        for line in &lines {
+            assert!(parse(line).is_err(), "{:?}", line);
+        }
+        let error = parse("a | | b").unwrap_err().to_string();
+        assert!(error.contains("1:5"), "{}", error);
+        assert!(error.contains("expected a command"), "{}", error);
+    }
+
+    #[test]
+    fn test_arg_tokens() {
+        parses_to! {
+            parser: CLIParser,
+            input: r#"-a\ 'b'"c\"""#,
+            rule: Rule::arg,
+            tokens: [
+                arg(0, 12, [
+                    plain(0, 2),
+                    escaped(2, 4),
+                    single_quoted(4, 7, [single_text(5, 6)]),
+                    double_quoted(7, 12, [double_text(8, 9), double_escaped(9, 11)])
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn test_redirect_tokens() {
+        parses_to! {
+            parser: CLIParser,
+            input: "2>'a b'",
+            rule: Rule::redirect,
+            tokens: [
+                redirect(0, 7, [
+                    stdio(0, 2),
+                    arg(2, 7, [single_quoted(2, 7, [single_text(3, 6)])])
+                ])
+            ]
+        };
+    }
+
+    #[test]
+    fn test_unterminated_quotes() {
+        // The line stops matching where the quote (or escape) starts
+        for input in &["echo 'a", "echo \\"] {
+            fails_with! {
+                parser: CLIParser,
+                input: input,
+                rule: Rule::line,
+                positives: vec![Rule::EOI, Rule::arg, Rule::redirect],
+                negatives: vec![],
+                pos: 5
+            };
+        }
+        // ...or at the end, for double quotes that could hold anything else
+        fails_with! {
+            parser: CLIParser,
+            input: "echo \"a",
+            rule: Rule::line,
+            positives: vec![Rule::double_escaped, Rule::double_text],
+            negatives: vec![],
+            pos: 7
+        };
+    }
 }
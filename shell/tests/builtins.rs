@@ -0,0 +1,158 @@
+//! Runs the builtins, and checks that they changed the shell itself, so that
+//! the commands after them see it.
+mod common;
+
+use common::{contents, run_session, run_shell, run_shell_with_errors};
+use std::fs;
+
+#[test]
+fn test_cd() {
+    let session = run_session(
+        "cd",
+        &["mkdir sub", "cd sub", "pwd > here.txt", "cd ..", "cd -"],
+    );
+    let dir = session.dir.canonicalize().unwrap();
+    let sub = dir.join("sub");
+    assert_eq!(contents(&sub, "here.txt"), format!("{}\n", sub.display()));
+
+    // The prompt shows each directory as soon as we're in it
+    let prompts: Vec<_> = session
+        .stdout
+        .split(" > ")
+        .filter_map(|prompt| prompt.rsplit(' ').next())
+        .collect();
+    let (dir, sub) = (dir.to_str().unwrap(), sub.to_str().unwrap());
+    assert_eq!(prompts[..5], [dir, dir, sub, sub, dir]);
+    assert!(session.stdout.contains(&format!("{}\n", sub)));
+    assert!(session.stdout.ends_with(&format!("{} > \n", sub)));
+    let _ = fs::remove_dir_all(&session.dir);
+}
+
+#[test]
+fn test_cd_errors() {
+    let (dir, errors) =
+        run_shell_with_errors("cd_errors", &["cd nosuchdir", "cd a b", "pwd > here.txt"]);
+    assert!(
+        errors.contains("cd: nosuchdir: No such file or directory"),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("cd: too many arguments"), "{}", errors);
+    let dir = dir.canonicalize().unwrap();
+    assert_eq!(contents(&dir, "here.txt"), format!("{}\n", dir.display()));
+    let _ = fs::remove_dir_all(&dir);
+
+    // `cd -` only prints the directory once it has changed to it
+    let session = run_session(
+        "cd_back_errors",
+        &["mkdir gone", "cd gone", "cd ..", "rmdir gone", "cd -"],
+    );
+    let gone = session.dir.canonicalize().unwrap().join("gone");
+    assert!(
+        session
+            .stderr
+            .contains(&format!("cd: {}: No such file", gone.display())),
+        "{}",
+        session.stderr
+    );
+    assert!(
+        !session.stdout.contains(&format!("{}\n", gone.display())),
+        "{}",
+        session.stdout
+    );
+    let _ = fs::remove_dir_all(&session.dir);
+}
+
+#[test]
+fn test_exit() {
+    let session = run_session("exit", &["exit 3", "echo after > after.txt"]);
+    assert_eq!(session.status.code(), Some(3));
+    assert!(!session.dir.join("after.txt").exists());
+    let _ = fs::remove_dir_all(&session.dir);
+
+    // A bad status is reported, and the shell carries on
+    let session = run_session("bad_exit", &["exit x", "echo after > after.txt"]);
+    assert!(session
+        .stderr
+        .contains("exit: x: numeric argument required"));
+    assert_eq!(contents(&session.dir, "after.txt"), "after\n");
+    assert_eq!(session.status.code(), Some(0));
+    let _ = fs::remove_dir_all(&session.dir);
+}
+
+#[test]
+fn test_export_and_unset() {
+    let (dir, errors) = run_shell_with_errors(
+        "export",
+        &[
+            "export GREETING='hi there' EMPTY=",
+            r#"sh -c "echo $GREETING; echo [$EMPTY]" > set.txt"#,
+            "unset GREETING",
+            r#"sh -c "echo [$GREETING]" > unset.txt"#,
+            "export 1=x",
+            "export | grep EMPTY > list.txt",
+        ],
+    );
+    assert_eq!(contents(&dir, "set.txt"), "hi there\n[]\n");
+    assert_eq!(contents(&dir, "unset.txt"), "[]\n");
+    assert_eq!(contents(&dir, "list.txt"), "export EMPTY=\"\"\n");
+    assert!(
+        errors.contains("export: `1=x': not a valid identifier"),
+        "{}",
+        errors
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_which() {
+    let (dir, errors) = run_shell_with_errors(
+        "which",
+        &[
+            "which cd sh nosuchcommand > which.txt",
+            "which which | cat > piped.txt",
+        ],
+    );
+    let found = contents(&dir, "which.txt");
+    let lines: Vec<_> = found.lines().collect();
+    assert_eq!(lines[0], "cd: shell builtin");
+    assert!(lines[1].ends_with("/sh"), "{}", found);
+    assert_eq!(lines.len(), 2);
+    assert!(
+        errors.contains("which: no nosuchcommand in $PATH"),
+        "{}",
+        errors
+    );
+    assert_eq!(contents(&dir, "piped.txt"), "which: shell builtin\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_builtin_into_pipe() {
+    let dir = run_shell("builtin_into_pipe", &["pwd | cat > pwd.txt 2>&1"]);
+    let dir = dir.canonicalize().unwrap();
+    assert_eq!(contents(&dir, "pwd.txt"), format!("{}\n", dir.display()));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_builtins_in_pipeline_leave_shell_alone() {
+    let session = run_session(
+        "builtins_in_pipeline",
+        &[
+            "mkdir sub",
+            "cd sub | cat",
+            "pwd > here.txt",
+            "export PIPED=yes | cat",
+            "sh -c \"echo [$PIPED]\" > env.txt",
+            "exit 3 | cat",
+            "echo after > after.txt",
+        ],
+    );
+    let dir = session.dir.canonicalize().unwrap();
+    assert_eq!(contents(&dir, "here.txt"), format!("{}\n", dir.display()));
+    assert_eq!(contents(&dir, "env.txt"), "[]\n");
+    assert_eq!(contents(&dir, "after.txt"), "after\n");
+    assert_eq!(session.status.code(), Some(0));
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,90 @@
+//! Helpers for running the shell from the integration tests
+#![allow(dead_code)] // Not every test uses every helper
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Run `lines` through the shell in a fresh directory, returning the directory
+pub fn run_shell(name: &str, lines: &[&str]) -> PathBuf {
+    run_shell_with_errors(name, lines).0
+}
+
+/// Run `lines` through the shell in a fresh directory, and wait for it to exit
+/// at the end of its input. Returns the directory, along with everything the
+/// shell wrote to stderr.
+pub fn run_shell_with_errors(name: &str, lines: &[&str]) -> (PathBuf, String) {
+    let session = run_session(name, lines);
+    (session.dir, session.stderr)
+}
+
+/// What a run of the shell left behind
+pub struct Session {
+    pub dir: PathBuf,
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+}
+
+/// Read all of `stream` on a thread of its own, so that the shell never blocks
+/// writing to it
+fn read_all<R: Read + Send + 'static>(mut stream: R) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stream.read_to_string(&mut text);
+        text
+    })
+}
+
+/// Run `lines` through the shell in a fresh directory, and wait for it to exit
+/// at the end of its input, or before
+pub fn run_session(name: &str, lines: &[&str]) -> Session {
+    let dir = std::env::temp_dir().join(format!("shell_{}.{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+
+    // The shell shouldn't need $USER for its prompt
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .current_dir(&dir)
+        .env_remove("USER")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not start the shell");
+
+    // The shell may exit before it has read all of this
+    let mut input = shell.stdin.take().unwrap();
+    for line in lines {
+        let _ = writeln!(input, "{}", line);
+    }
+    drop(input);
+    let stdout = read_all(shell.stdout.take().unwrap());
+    let stderr = read_all(shell.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = shell.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            let _ = shell.kill();
+            let _ = shell.wait();
+            panic!("The shell didn't exit at the end of its input");
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    Session {
+        dir,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+        status,
+    }
+}
+
+pub fn contents(dir: &Path, file: &str) -> String {
+    fs::read_to_string(dir.join(file)).unwrap()
+}
@@ -0,0 +1,39 @@
+//! Feeds the shell lines that it can't parse or run, and checks that it says
+//! so and carries on with the next line.
+mod common;
+
+use common::{contents, run_shell_with_errors};
+use std::fs;
+
+#[test]
+fn test_parse_errors() {
+    let (dir, errors) = run_shell_with_errors(
+        "parse_errors",
+        &["ls ;", "| cat", "echo a | | b", "echo ok > ok.txt"],
+    );
+    assert_eq!(contents(&dir, "ok.txt"), "ok\n");
+
+    // Each error points at where the line went wrong
+    assert!(errors.contains(" --> 1:4\n"), "{}", errors);
+    assert!(
+        errors.contains("= expected the end of the line"),
+        "{}",
+        errors
+    );
+    assert!(errors.contains(" --> 1:1\n"), "{}", errors);
+    assert!(errors.contains("= expected a pipeline"), "{}", errors);
+    assert!(errors.contains(" --> 1:10\n"), "{}", errors);
+    assert!(errors.contains("= expected a command"), "{}", errors);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_bad_commands() {
+    let (dir, errors) = run_shell_with_errors(
+        "bad_commands",
+        &["nosuchcommand", "cat < nosuchfile", "", "echo ok > ok.txt"],
+    );
+    assert_eq!(contents(&dir, "ok.txt"), "ok\n");
+    assert_eq!(errors.matches("No such file or directory").count(), 2);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,56 @@
+//! Runs pipelines through the shell, and checks what comes out of their last
+//! stage, and that none of their stages is left behind.
+mod common;
+
+use common::{contents, run_shell};
+use std::fs;
+
+#[test]
+fn test_multi_stage() {
+    let dir = run_shell("multi_stage", &["seq 1 5 | sort -r | head -2 > top.txt"]);
+    assert_eq!(contents(&dir, "top.txt"), "5\n4\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stages_run_at_once() {
+    // yes never finishes, so head has to be reading while it runs
+    let dir = run_shell("at_once", &["yes | head -3 > y.txt"]);
+    assert_eq!(contents(&dir, "y.txt"), "y\ny\ny\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stderr_into_pipe() {
+    let dir = run_shell(
+        "stderr_into_pipe",
+        &[r#"sh -c "echo out; echo err 1>&2" 2>&1 | sort > all.txt"#],
+    );
+    assert_eq!(contents(&dir, "all.txt"), "err\nout\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_missing_stage() {
+    let dir = run_shell(
+        "missing_stage",
+        &["nosuchcommand | sh -c \"cat; echo after\" > after.txt"],
+    );
+    assert_eq!(contents(&dir, "after.txt"), "after\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stages_are_reaped() {
+    // The first stage leaves its pid behind. Once the pipeline is done, there
+    // shouldn't even be a zombie with that pid.
+    let dir = run_shell(
+        "reaped",
+        &[
+            r#"sh -c "echo $$ > pid.txt" | cat"#,
+            r#"sh -c "cat /proc/$(cat pid.txt)/stat > stat.txt 2>&1 || echo gone > stat.txt""#,
+        ],
+    );
+    assert_eq!(contents(&dir, "stat.txt"), "gone\n");
+    let _ = fs::remove_dir_all(&dir);
+}
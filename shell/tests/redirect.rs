@@ -0,0 +1,66 @@
+//! Runs the shell on a few command lines, in a directory of their own, and
+//! checks what each redirection operator left in the files there.
+mod common;
+
+use common::{contents, run_shell};
+use std::fs;
+
+/// A command that prints a line to stdout and another to stderr
+const FIXTURE: &str = r#"sh -c "echo out; echo err 1>&2""#;
+
+#[test]
+fn test_stdout() {
+    let dir = run_shell(
+        "stdout",
+        &[
+            "echo stale line > out.txt",
+            &format!("{} > out.txt", FIXTURE),
+        ],
+    );
+    assert_eq!(contents(&dir, "out.txt"), "out\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_append() {
+    let dir = run_shell(
+        "append",
+        &["echo one > log.txt", &format!("{} >> log.txt", FIXTURE)],
+    );
+    assert_eq!(contents(&dir, "log.txt"), "one\nout\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stderr() {
+    let dir = run_shell("stderr", &[&format!("{} 2> err.txt", FIXTURE)]);
+    assert_eq!(contents(&dir, "err.txt"), "err\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stdout_and_stderr() {
+    let dir = run_shell("both", &[&format!("{} &> all.txt", FIXTURE)]);
+    assert_eq!(contents(&dir, "all.txt"), "out\nerr\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stderr_to_stdout() {
+    let dir = run_shell(
+        "stderr_to_stdout",
+        &[&format!("{} > all.txt 2>&1", FIXTURE)],
+    );
+    assert_eq!(contents(&dir, "all.txt"), "out\nerr\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stdout_to_stderr() {
+    let dir = run_shell(
+        "stdout_to_stderr",
+        &[&format!("{} 2> all.txt 1>&2", FIXTURE)],
+    );
+    assert_eq!(contents(&dir, "all.txt"), "out\nerr\n");
+    let _ = fs::remove_dir_all(&dir);
+}
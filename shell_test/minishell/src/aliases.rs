@@ -0,0 +1,149 @@
+/// Aliases, as set by `alias ll='ls -l'`. When the first word of a simple
+/// command is an alias, the alias's value takes its place, and the result is
+/// run instead.
+/* Notes:
+  Aliases are expanded just before a command runs, rather than as the line is
+  parsed, so the value of an alias can be any command line at all, `;`s and
+  `&&`s included.
+
+  An alias isn't expanded again inside its own value, which is what makes
+  `alias ls='ls -F'` work, and stops `alias a=b b=a` from going around forever.
+*/
+use crate::vars::is_valid_name;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct Aliases {
+    aliases: BTreeMap<String, String>,
+    expanding: Vec<String>, // The aliases being run right now, innermost last
+}
+
+impl Aliases {
+    pub const fn new() -> Self {
+        Aliases {
+            aliases: BTreeMap::new(),
+            expanding: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(|x| &x[..])
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.aliases.insert(name.to_string(), value.to_string());
+    }
+
+    /// Remove an alias, returning false if there wasn't one
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.aliases.clear();
+    }
+
+    /// The aliases, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.aliases.iter()
+    }
+
+    /// If the command name in `words` (the first word that isn't a `NAME=value`
+    /// assignment) is an alias that isn't already being expanded, returns the
+    /// alias and the command line it expands to.
+    pub fn expand(&self, words: &[String]) -> Option<(String, String)> {
+        let position = words.iter().position(|word| !is_assignment(word))?;
+        let name = &words[position];
+        if self.expanding.contains(name) {
+            return None;
+        }
+        let value = self.get(name)?;
+
+        let mut line: Vec<&str> = words[..position].iter().map(|x| &x[..]).collect();
+        line.push(value);
+        line.extend(words[position + 1..].iter().map(|x| &x[..]));
+        Some((name.clone(), line.join(" ")))
+    }
+
+    /// Note that `name` is being expanded until the matching finish_expanding()
+    pub fn start_expanding(&mut self, name: String) {
+        self.expanding.push(name);
+    }
+
+    pub fn finish_expanding(&mut self) {
+        self.expanding.pop();
+    }
+}
+
+/// Whether a word, as typed, is a `NAME=value` assignment
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(eq) => is_valid_name(&word[..eq]),
+        None => false,
+    }
+}
+
+/// Whether `name` can be used as an alias: any word that doesn't need quoting,
+/// and has no `/`, `=` or `$` in it
+pub fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "/=$`\\'\"&|;()<>#".contains(c))
+}
+
+static mut ALIASES: Aliases = Aliases::new();
+
+/// Get a handle to the shell's aliases
+pub fn aliases() -> &'static mut Aliases {
+    unsafe { &mut *std::ptr::addr_of_mut!(ALIASES) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split(' ').map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut aliases = Aliases::new();
+        aliases.set("ll", "ls -l");
+        aliases.set("both", "a && b");
+        assert_eq!(
+            aliases.expand(&words("ll -a dir")),
+            Some(("ll".to_string(), "ls -l -a dir".to_string()))
+        );
+        assert_eq!(
+            aliases.expand(&words("A=1 both")),
+            Some(("both".to_string(), "A=1 a && b".to_string()))
+        );
+        // Only the command name is expanded, and only as typed
+        assert_eq!(aliases.expand(&words("echo ll")), None);
+        assert_eq!(aliases.expand(&words("'ll'")), None);
+        assert_eq!(aliases.expand(&words("A=1")), None);
+
+        aliases.start_expanding("ll".to_string());
+        assert_eq!(aliases.expand(&words("ll")), None);
+        aliases.finish_expanding();
+        assert!(aliases.expand(&words("ll")).is_some());
+
+        assert!(aliases.remove("ll"));
+        assert!(!aliases.remove("ll"));
+        assert_eq!(aliases.expand(&words("ll")), None);
+    }
+
+    #[test]
+    fn test_alias_names() {
+        assert!(is_valid_alias_name("ll"));
+        assert!(is_valid_alias_name("git-st"));
+        assert!(is_valid_alias_name("..."));
+        assert!(!is_valid_alias_name(""));
+        assert!(!is_valid_alias_name("a=b"));
+        assert!(!is_valid_alias_name("/bin/ls"));
+        assert!(!is_valid_alias_name("$x"));
+        assert!(!is_valid_alias_name("a b"));
+    }
+}
@@ -0,0 +1,573 @@
+/// The structure of a command line: lists of commands separated by `;` and `&`,
+/// conditionals joined by `&&` and `||`, `( ... )` subshells, `{ ...; }` groups
+/// and `name() { ...; }` function definitions. The parser builds this from the
+/// tokens of util::lex().
+/* Notes:
+  Words are kept exactly as they were typed, quotes and all. They're only
+  expanded when the command that holds them is about to run, so that in
+  `cd dir; echo $PWD` the echo sees the new directory.
+
+  Every command and list item remembers the text it was parsed from, since
+  that's what the job list shows for it.
+
+  `{` and `}` aren't operators, like `(` and `)` are, but reserved words: they
+  only mean something as the first word of a command, so `echo }` still echoes
+  a `}`. That's why a group needs a `;` before its closing brace.
+
+  A newline ends a list item like `;` does, and blank lines are skipped
+  wherever a command could start, so a script's group, subshell or function
+  body can span several lines. When a line leaves one of them open, parsing
+  fails with an error that is_incomplete(), and the caller can try again once
+  it has read more lines.
+*/
+use crate::util::{lex, ParseFailReason, Token, OPERATORS};
+use crate::vars::is_valid_name;
+
+/// A single command, and the text of the line that it came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub text: String,
+    pub timed: bool, // Prefixed with the `time` keyword, which isn't in the text
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandKind {
+    Simple(Vec<String>), // A program or builtin, with any NAME=value prefixes
+    Subshell(List),      // A list run by a child copy of the shell
+    Group(List),         // A list run by the shell itself
+    FunctionDef(String, Box<Command>), // A function's name and body
+}
+
+/// How a command in an AndOr is joined to the one before it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Connector {
+    And, // Run only if the previous command succeeded
+    Or,  // Run only if the previous command failed
+}
+
+/// Commands joined by `&&` and `||`, which are evaluated left to right
+#[derive(Clone, Debug, PartialEq)]
+pub struct AndOr {
+    pub first: Command,
+    pub rest: Vec<(Connector, Command)>,
+}
+
+/// One entry of a List, terminated by `;` or `&`. The text of a background
+/// item includes the `&`, as the job list has always shown it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub background: bool,
+    pub text: String,
+}
+
+/// A sequence of AndOrs, which are run one after the other
+#[derive(Clone, Debug, PartialEq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+/// Parse a command line, or several. Lines with nothing to run (blank, or only
+/// a comment) are reported as EmptyLine.
+pub fn parse(line: &str) -> Result<List, ParseFailReason> {
+    let tokens = lex(line)?;
+    if tokens.iter().all(|token| token.text == "\n") {
+        return Err(ParseFailReason::EmptyLine);
+    }
+
+    let mut parser = Parser {
+        line,
+        tokens,
+        pos: 0,
+    };
+    let list = parser.list()?;
+    match parser.peek() {
+        None => Ok(list),
+        Some(token) => Err(unexpected(token)), // e.g. an unmatched `)`
+    }
+}
+
+/// Split a script, or a sourced file, into the commands to evaluate one after
+/// the other. Each is a single line, unless it leaves a quote, group, subshell
+/// or function body open, in which case it takes in the lines after it up to
+/// the one that closes it. Whatever is still open at the end of the script is
+/// the last command, so that evaluating it reports the error.
+pub fn split_script(script: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut command = String::new();
+    for line in script.lines() {
+        if !command.is_empty() {
+            command.push('\n');
+        }
+        command.push_str(line);
+        match parse(&command) {
+            Err(e) if e.is_incomplete() => continue,
+            _ => commands.push(std::mem::take(&mut command)),
+        }
+    }
+    if !command.is_empty() {
+        commands.push(command);
+    }
+    commands
+}
+
+/// The error for a token that isn't allowed where it was found
+fn unexpected(token: &str) -> ParseFailReason {
+    ParseFailReason::Invalid(token.chars().next().unwrap_or(' '))
+}
+
+/// A recursive descent parser over the tokens of a line
+struct Parser<'a> {
+    line: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| &token.text[..])
+    }
+
+    /// Where the next token starts, or the end of the line
+    fn next_start(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.line.len(), |x| x.start)
+    }
+
+    /// Where the last token consumed ends
+    fn last_end(&self) -> usize {
+        self.tokens[self.pos - 1].end
+    }
+
+    /// Skip any newlines, where a command could start
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some("\n") {
+            self.pos += 1;
+        }
+    }
+
+    fn list(&mut self) -> Result<List, ParseFailReason> {
+        let mut items = Vec::new();
+        self.skip_newlines();
+        while !matches!(self.peek(), None | Some(")") | Some("}")) {
+            let start = self.next_start();
+            let and_or = self.and_or()?;
+            let background = self.peek() == Some("&");
+            let terminated = matches!(self.peek(), Some("&") | Some(";") | Some("\n"));
+
+            // The text of a background item includes its &, but not a ;
+            let mut end = self.last_end();
+            if terminated {
+                self.pos += 1;
+                if background {
+                    end = self.last_end();
+                }
+            }
+            items.push(ListItem {
+                and_or,
+                background,
+                text: self.line[start..end].to_string(),
+            });
+            if !terminated {
+                break;
+            }
+            self.skip_newlines();
+        }
+
+        if items.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => ParseFailReason::UnexpectedEnd,
+            });
+        }
+        Ok(List { items })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseFailReason> {
+        let first = self.command()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some("&&") => Connector::And,
+                Some("||") => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.command()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseFailReason> {
+        // `time` is only a keyword if there's a command after it to time
+        let timed = self.peek() == Some("time")
+            && matches!(self.tokens.get(self.pos + 1), Some(token)
+                if token.text == "(" || !OPERATORS.contains(&&token.text[..]));
+        if timed {
+            self.pos += 1;
+        }
+        let start = self.next_start();
+
+        if self.peek() == Some("(") {
+            self.pos += 1;
+            let list = self.list()?;
+            match self.peek() {
+                Some(")") => {}
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(ParseFailReason::Unmatched('(')),
+            }
+            self.pos += 1;
+            return Ok(Command {
+                kind: CommandKind::Subshell(list),
+                text: self.line[start..self.last_end()].to_string(),
+                timed,
+            });
+        }
+
+        if self.peek() == Some("{") {
+            self.pos += 1;
+            let list = self.list()?;
+            match self.peek() {
+                Some("}") => {}
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(ParseFailReason::Unmatched('{')),
+            }
+            self.pos += 1;
+            return Ok(Command {
+                kind: CommandKind::Group(list),
+                text: self.line[start..self.last_end()].to_string(),
+                timed,
+            });
+        }
+
+        if let Some(name) = self.function_name() {
+            self.pos += 3;
+            self.skip_newlines();
+            // The body has to be a group or a subshell
+            let body = match self.peek() {
+                Some("{") | Some("(") => self.command()?,
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(ParseFailReason::UnexpectedEnd),
+            };
+            return Ok(Command {
+                kind: CommandKind::FunctionDef(name, Box::new(body)),
+                text: self.line[start..self.last_end()].to_string(),
+                timed,
+            });
+        }
+
+        let mut words = Vec::new();
+        while let Some(token) = self.peek() {
+            if OPERATORS.contains(&token) {
+                break;
+            }
+            words.push(token.to_string());
+            self.pos += 1;
+        }
+        if words.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => ParseFailReason::UnexpectedEnd,
+            });
+        }
+        Ok(Command {
+            kind: CommandKind::Simple(words),
+            text: self.line[start..self.last_end()].to_string(),
+            timed,
+        })
+    }
+
+    /// If the next tokens are `name ( )`, the start of a function definition,
+    /// returns the name
+    fn function_name(&self) -> Option<String> {
+        let tokens = self.tokens.get(self.pos..self.pos + 3)?;
+        if tokens[1].text == "(" && tokens[2].text == ")" && is_valid_name(&tokens[0].text) {
+            Some(tokens[0].text.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simple(words: &[&str], text: &str) -> Command {
+        Command {
+            kind: CommandKind::Simple(words.iter().map(|x| x.to_string()).collect()),
+            text: text.to_string(),
+            timed: false,
+        }
+    }
+
+    fn item(first: Command, rest: Vec<(Connector, Command)>, bg: bool, text: &str) -> ListItem {
+        ListItem {
+            and_or: AndOr { first, rest },
+            background: bg,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_single_command() {
+        let list = parse("./myspin 1 &\n").unwrap();
+        assert_eq!(
+            list.items,
+            vec![item(
+                simple(&["./myspin", "1"], "./myspin 1"),
+                vec![],
+                true,
+                "./myspin 1 &"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_sequence() {
+        let list = parse("a 1; b&c ;").unwrap();
+        assert_eq!(
+            list.items,
+            vec![
+                item(simple(&["a", "1"], "a 1"), vec![], false, "a 1"),
+                item(simple(&["b"], "b"), vec![], true, "b&"),
+                item(simple(&["c"], "c"), vec![], false, "c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_and_or() {
+        let list = parse("a && b || 'c d' &").unwrap();
+        assert_eq!(
+            list.items,
+            vec![item(
+                simple(&["a"], "a"),
+                vec![
+                    (Connector::And, simple(&["b"], "b")),
+                    (Connector::Or, simple(&["'c d'"], "'c d'")),
+                ],
+                true,
+                "a && b || 'c d' &"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_subshell() {
+        let list = parse("(cd dir; make) && echo ok").unwrap();
+        let inner = List {
+            items: vec![
+                item(simple(&["cd", "dir"], "cd dir"), vec![], false, "cd dir"),
+                item(simple(&["make"], "make"), vec![], false, "make"),
+            ],
+        };
+        assert_eq!(
+            list.items,
+            vec![item(
+                Command {
+                    kind: CommandKind::Subshell(inner),
+                    text: "(cd dir; make)".to_string(),
+                    timed: false,
+                },
+                vec![(Connector::And, simple(&["echo", "ok"], "echo ok"))],
+                false,
+                "(cd dir; make) && echo ok"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let list = parse("time make -j4 && time (a) || time").unwrap();
+        let timed = |command: Command| Command {
+            timed: true,
+            ..command
+        };
+        let subshell = Command {
+            kind: CommandKind::Subshell(List {
+                items: vec![item(simple(&["a"], "a"), vec![], false, "a")],
+            }),
+            text: "(a)".to_string(),
+            timed: true,
+        };
+        assert_eq!(
+            list.items,
+            vec![item(
+                timed(simple(&["make", "-j4"], "make -j4")),
+                vec![
+                    (Connector::And, subshell),
+                    (Connector::Or, simple(&["time"], "time")),
+                ],
+                false,
+                "time make -j4 && time (a) || time"
+            )]
+        );
+        // Only a bare `time` at the start of a command is the keyword
+        let list = parse("'time' a; echo time").unwrap();
+        assert!(!list.items[0].and_or.first.timed);
+        assert!(!list.items[1].and_or.first.timed);
+    }
+
+    #[test]
+    fn test_group() {
+        let list = parse("{ a; b & } || echo }").unwrap();
+        let inner = List {
+            items: vec![
+                item(simple(&["a"], "a"), vec![], false, "a"),
+                item(simple(&["b"], "b"), vec![], true, "b &"),
+            ],
+        };
+        assert_eq!(
+            list.items,
+            vec![item(
+                Command {
+                    kind: CommandKind::Group(inner),
+                    text: "{ a; b & }".to_string(),
+                    timed: false,
+                },
+                vec![(Connector::Or, simple(&["echo", "}"], "echo }"))],
+                false,
+                "{ a; b & } || echo }"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_function_def() {
+        let list = parse("greet() { echo hi $1; }; greet you").unwrap();
+        let body = Command {
+            kind: CommandKind::Group(List {
+                items: vec![item(
+                    simple(&["echo", "hi", "$1"], "echo hi $1"),
+                    vec![],
+                    false,
+                    "echo hi $1",
+                )],
+            }),
+            text: "{ echo hi $1; }".to_string(),
+            timed: false,
+        };
+        let def = Command {
+            kind: CommandKind::FunctionDef("greet".to_string(), Box::new(body)),
+            text: "greet() { echo hi $1; }".to_string(),
+            timed: false,
+        };
+        assert_eq!(
+            list.items,
+            vec![
+                item(def, vec![], false, "greet() { echo hi $1; }"),
+                item(
+                    simple(&["greet", "you"], "greet you"),
+                    vec![],
+                    false,
+                    "greet you"
+                ),
+            ]
+        );
+
+        let list = parse("f () (cd /tmp)").unwrap();
+        match &list.items[0].and_or.first.kind {
+            CommandKind::FunctionDef(name, body) => {
+                assert_eq!(name, "f");
+                assert_eq!(body.text, "(cd /tmp)");
+            }
+            other => panic!("not a function definition: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_quoted_operators() {
+        let list = parse("echo ';' \\& \"&&\" a#b # c; d").unwrap();
+        assert_eq!(
+            list.items,
+            vec![item(
+                simple(
+                    &["echo", "';'", "\\&", "\"&&\"", "a#b"],
+                    "echo ';' \\& \"&&\" a#b"
+                ),
+                vec![],
+                false,
+                "echo ';' \\& \"&&\" a#b"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("   # nothing"), Err(ParseFailReason::EmptyLine));
+        assert_eq!(parse("; a"), Err(ParseFailReason::Invalid(';')));
+        assert_eq!(parse("a && && b"), Err(ParseFailReason::Invalid('&')));
+        assert_eq!(parse("a ||"), Err(ParseFailReason::UnexpectedEnd));
+        assert_eq!(parse("(a; b"), Err(ParseFailReason::Unmatched('(')));
+        assert_eq!(parse("a)"), Err(ParseFailReason::Invalid(')')));
+        assert_eq!(parse("()"), Err(ParseFailReason::Invalid(')')));
+        assert_eq!(parse("(a) b"), Err(ParseFailReason::Invalid('b')));
+        assert_eq!(parse("a | b"), Err(ParseFailReason::Invalid('|')));
+        assert_eq!(parse("echo 'a"), Err(ParseFailReason::Unmatched('\'')));
+        assert_eq!(parse("{ a; b"), Err(ParseFailReason::Unmatched('{')));
+        assert_eq!(parse("{ a }"), Err(ParseFailReason::Unmatched('{')));
+        assert_eq!(parse("a; }"), Err(ParseFailReason::Invalid('}')));
+        assert_eq!(parse("f() a"), Err(ParseFailReason::Invalid('a')));
+        assert_eq!(parse("f()"), Err(ParseFailReason::UnexpectedEnd));
+        assert_eq!(parse("1f() { a; }"), Err(ParseFailReason::Invalid('(')));
+        assert_eq!(parse("{ a; )"), Err(ParseFailReason::Invalid(')')));
+        assert_eq!(parse("(a; }"), Err(ParseFailReason::Invalid('}')));
+    }
+
+    #[test]
+    fn test_multiple_lines() {
+        let list = parse("\nf()\n{\n  a 1\n  b &\n}\n\nc &&\n  d\n").unwrap();
+        let body = Command {
+            kind: CommandKind::Group(List {
+                items: vec![
+                    item(simple(&["a", "1"], "a 1"), vec![], false, "a 1"),
+                    item(simple(&["b"], "b"), vec![], true, "b &"),
+                ],
+            }),
+            text: "{\n  a 1\n  b &\n}".to_string(),
+            timed: false,
+        };
+        let def = Command {
+            kind: CommandKind::FunctionDef("f".to_string(), Box::new(body)),
+            text: "f()\n{\n  a 1\n  b &\n}".to_string(),
+            timed: false,
+        };
+        assert_eq!(
+            list.items,
+            vec![
+                item(def, vec![], false, "f()\n{\n  a 1\n  b &\n}"),
+                item(
+                    simple(&["c"], "c"),
+                    vec![(Connector::And, simple(&["d"], "d"))],
+                    false,
+                    "c &&\n  d"
+                ),
+            ]
+        );
+        assert_eq!(parse("\n # nothing\n\n"), Err(ParseFailReason::EmptyLine));
+        assert_eq!(parse("a\n)"), Err(ParseFailReason::Invalid(')')));
+    }
+
+    #[test]
+    fn test_incomplete() {
+        for line in &["{ a", "(a;", "f() {", "f()", "a &&", "echo 'a", "(a\nb"] {
+            assert!(parse(line).unwrap_err().is_incomplete(), "{}", line);
+        }
+        for line in &["a )", "; a", "{ a; )"] {
+            assert!(!parse(line).unwrap_err().is_incomplete(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_split_script() {
+        let script = "a\n\nf() {\n  b\n}\necho 'x\ny'; (c\n)\nd\n{ e\n";
+        assert_eq!(
+            split_script(script),
+            vec!["a", "", "f() {\n  b\n}", "echo 'x\ny'; (c\n)", "d", "{ e"]
+        );
+    }
+}
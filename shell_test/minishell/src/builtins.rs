@@ -0,0 +1,1011 @@
+/// The builtin commands of rustymsh. Builtins run inside the shell process, and
+/// run_simple() calls them with job signals blocked, so they can safely inspect
+/// and modify the joblist.
+use crate::aliases::{aliases, is_valid_alias_name};
+use crate::ast::split_script;
+use crate::dirs::{change_dir, current_dir, dir_stack, search_cdpath, tilde_abbrev};
+use crate::errors::ShellError;
+use crate::functions::functions;
+use crate::history::history;
+use crate::jobs::{JobSpec, JobState, JobStruct};
+use crate::resources::{find_limit, format_duration, get_limit, set_limit, CpuTimes, LIMITS};
+use crate::terminal;
+use crate::traps::{parse_trap, reset_trap, set_trap, trap_name, traps};
+use crate::util::{parse_jobspec, parse_signal, ExpandEnv};
+use crate::vars::{is_valid_name, quote, vars};
+use crate::{eval, exit_shell, joblist, wait_until, waitfg};
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+/// A builtin command, taking its argv and returning its exit status
+type Builtin = fn(&[String]) -> i32;
+
+/// The names of all the builtins, for tab completion
+pub const BUILTINS: &[&str] = &[
+    "jobs", "quit", "exit", "fg", "bg", "kill", "wait", "disown", "export", "unset", "env", "set",
+    "cd", "pwd", "pushd", "popd", "dirs", "source", ".", "shift", "history", "times", "ulimit",
+    "alias", "unalias", "declare", "trap",
+];
+
+/// Find the builtin called `name`, if there is one
+fn lookup_builtin(name: &str) -> Option<Builtin> {
+    let builtin: Builtin = match name {
+        "jobs" => do_jobs,
+        "quit" | "exit" => do_exit,
+        "fg" | "bg" => do_bgfg,
+        "kill" => do_kill,
+        "wait" => do_wait,
+        "disown" => do_disown,
+        "export" => do_export,
+        "unset" => do_unset,
+        "env" => do_env,
+        "set" => do_set,
+        "cd" => do_cd,
+        "pwd" => do_pwd,
+        "pushd" => do_pushd,
+        "popd" => do_popd,
+        "dirs" => do_dirs,
+        "source" | "." => do_source,
+        "shift" => do_shift,
+        "history" => do_history,
+        "times" => do_times,
+        "ulimit" => do_ulimit,
+        "alias" => do_alias,
+        "unalias" => do_unalias,
+        "declare" => do_declare,
+        "trap" => do_trap,
+        _ => return None,
+    };
+    Some(builtin)
+}
+
+/// Whether `name` is the name of a builtin command
+pub fn is_builtin(name: &str) -> bool {
+    lookup_builtin(name).is_some()
+}
+
+/// Checks to see if argv corresponds to built-in, and executes it if so.
+/// Returns the exit status of the builtin, or None if it wasn't one.
+pub fn builtin_cmd(argv: &[String]) -> Option<i32> {
+    lookup_builtin(&argv[0]).map(|builtin| builtin(argv))
+}
+
+/// Leave the shell: `exit [n]`, or `quit [n]`. The status defaults to that of
+/// the last command. The EXIT trap runs first.
+fn do_exit(argv: &[String]) -> i32 {
+    let status = match argv.get(1) {
+        None => vars().last_status(),
+        Some(_) if argv.len() > 2 => {
+            complain(format!("{}: too many arguments", argv[0]));
+            return 1;
+        }
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                complain(format!("{}: {}: numeric argument required", argv[0], arg));
+                2
+            }
+        },
+    };
+    vars().set_last_status(status);
+    exit_shell();
+}
+
+/// Report a builtin's complaint about its arguments, or about what it was
+/// asked to do. The builtin still returns its own status.
+fn complain(message: impl Into<String>) {
+    ShellError::Builtin(message.into()).report();
+}
+
+/// Report the mshref-style complaint about a job specification that doesn't
+/// match any job.
+fn no_such_job(spec: JobSpec) {
+    match spec {
+        JobSpec::Pid(pid) => complain(format!("({}): No such process", pid)),
+        JobSpec::Jid(jid) => complain(format!("%{}: No such job", jid)),
+    }
+}
+
+/// Look up the job named by a PID or %jobid argument to the builtin `cmd`.
+/// Prints an error message and returns None if there is no such job.
+fn lookup_job(cmd: &str, arg: &str) -> Option<&'static mut JobStruct> {
+    let spec = match parse_jobspec(arg) {
+        Ok(spec) => spec,
+        Err(_) => {
+            complain(format!("{}: argument must be a PID or %jobid", cmd));
+            return None;
+        }
+    };
+
+    let job = joblist().getjob(spec);
+    if job.is_none() {
+        no_such_job(spec);
+    }
+    job
+}
+
+/// The options of the jobs builtin
+#[derive(Debug, Default, PartialEq)]
+struct JobsOptions {
+    pids_only: bool,
+    running: bool,
+    stopped: bool,
+}
+
+impl JobsOptions {
+    /// Parse the arguments of `jobs`, returning the first one that isn't a
+    /// valid option as the error
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = JobsOptions::default();
+        for arg in args {
+            let flags = match arg.strip_prefix('-') {
+                Some(flags) if !flags.is_empty() => flags,
+                _ => return Err(arg.to_string()),
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'l' => {}
+                    'p' => options.pids_only = true,
+                    'r' => options.running = true,
+                    's' => options.stopped = true,
+                    _ => return Err(format!("-{}", flag)),
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// List the jobs: `jobs [-lprs]`. `-p` prints only PIDs, while `-r` and `-s`
+/// restrict the listing to running or stopped jobs. `-l` is accepted for
+/// compatibility, but the default listing already includes the PID.
+fn do_jobs(argv: &[String]) -> i32 {
+    let JobsOptions {
+        pids_only,
+        running,
+        stopped,
+    } = match JobsOptions::parse(&argv[1..]) {
+        Ok(options) => options,
+        Err(arg) => {
+            complain(format!("jobs: {}: invalid option", arg));
+            complain("jobs: usage: jobs [-lprs]");
+            return 1;
+        }
+    };
+
+    // Asking for neither running nor stopped jobs means asking for all of them
+    let show_all = !running && !stopped;
+    for job in joblist().iter() {
+        let shown = match job.state() {
+            JobState::BG => running || show_all,
+            JobState::Stop => stopped || show_all,
+            JobState::FG | JobState::Done(_) | JobState::Killed(_) => show_all,
+        };
+        if !shown {
+            continue;
+        }
+
+        if pids_only {
+            println!("{}", job.pid());
+        } else {
+            println!("{}", job);
+        }
+    }
+    0
+}
+
+/// Handles moving jobs into the foreground and running jobs in the background.
+fn do_bgfg(argv: &[String]) -> i32 {
+    if argv.len() == 1 {
+        complain(format!(
+            "{} command requires PID or %jobid argument",
+            argv[0]
+        ));
+        return 1;
+    }
+
+    let tofg = match &argv[0][..] {
+        "fg" => true,
+        "bg" => false,
+        // This function should only be entered if argv[0] is fg or bg, but I don't
+        // want to rely on someone never changing the caller code...
+        _ => unreachable!(),
+    };
+
+    let job = match lookup_job(&argv[0], &argv[1]) {
+        Some(job) => job,
+        None => return 1,
+    };
+
+    if let JobState::Done(_) | JobState::Killed(_) = job.state() {
+        complain(format!("{}: job has terminated", argv[0]));
+        return 1;
+    }
+
+    let jid = job.jid();
+    let pid = job.pid();
+    let cmdline = job.cmdline().to_string();
+
+    // A job resumed in the foreground needs the terminal before it runs, or it
+    // may stop again as soon as it touches the terminal.
+    if tofg {
+        terminal::give_to(pid, job.tmodes());
+    }
+
+    let group_id = Pid::from_raw(-pid.as_raw());
+    if kill(group_id, Signal::SIGCONT).is_err() {
+        ShellError::Sys("kill", Errno::last()).report();
+        terminal::reclaim();
+        return 1;
+    }
+
+    if tofg {
+        job.set_state(JobState::FG);
+        waitfg(pid)
+    } else {
+        job.set_state(JobState::BG);
+        println!("[{}] ({}) {}", jid, pid, cmdline.trim());
+        0
+    }
+}
+
+/// Send a signal to jobs or processes: `kill [-SIG] %jobid|pid ...`. The signal
+/// may be given by number or by name, and defaults to SIGTERM. A %jobid signals
+/// the whole process group of the job, while a PID signals only that process.
+fn do_kill(argv: &[String]) -> i32 {
+    let mut args = &argv[1..];
+    let mut signal = Signal::SIGTERM;
+
+    if let Some(name) = args.first().and_then(|arg| arg.strip_prefix('-')) {
+        signal = match parse_signal(name) {
+            Some(sig) => sig,
+            None => {
+                complain(format!("kill: {}: invalid signal specification", name));
+                return 1;
+            }
+        };
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        complain("kill command requires PID or %jobid argument");
+        return 1;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let spec = match parse_jobspec(arg) {
+            Ok(spec) => spec,
+            Err(_) => {
+                complain("kill: argument must be a PID or %jobid");
+                status = 1;
+                continue;
+            }
+        };
+
+        let stopped = joblist()
+            .getjob(spec)
+            .is_some_and(|job| job.state() == JobState::Stop);
+        let target = match spec {
+            JobSpec::Pid(pid) => pid,
+            JobSpec::Jid(_) => match joblist().getjob(spec) {
+                Some(job) => Pid::from_raw(-job.pid().as_raw()),
+                None => {
+                    no_such_job(spec);
+                    status = 1;
+                    continue;
+                }
+            },
+        };
+
+        match kill(target, signal) {
+            Ok(()) => {
+                // A stopped job won't act on a termination request until it
+                // is continued, so give it a nudge like bash does.
+                if stopped && (signal == Signal::SIGTERM || signal == Signal::SIGHUP) {
+                    let _ = kill(target, Signal::SIGCONT);
+                }
+            }
+            Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => {
+                no_such_job(spec);
+                status = 1;
+            }
+            Err(e) => {
+                complain(format!("kill: {}: {}", arg, e));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Wait for background jobs to finish: `wait [%jobid|pid ...]`. With no
+/// arguments, waits until none of the jobs are running. A job that stops also
+/// ends the wait, since it would otherwise never finish.
+fn do_wait(argv: &[String]) -> i32 {
+    if argv.len() == 1 {
+        wait_until(|jobs| jobs.iter().all(|job| job.state() != JobState::BG));
+        return 0;
+    }
+
+    // Like bash, the status is that of the last job waited for
+    let mut status = 0;
+    for arg in &argv[1..] {
+        let pid = match lookup_job("wait", arg) {
+            Some(job) => job.pid(),
+            None => {
+                status = 127;
+                continue;
+            }
+        };
+        wait_until(|jobs| {
+            jobs.iter()
+                .find(|job| job.pid() == pid)
+                .is_none_or(|job| job.state() != JobState::BG)
+        });
+        status = match joblist().getjob_pid(pid).map(|job| job.state()) {
+            Some(JobState::Done(code)) | Some(JobState::Killed(code)) => code,
+            _ => 0,
+        };
+    }
+    status
+}
+
+/// Remove jobs from the joblist without signalling them:
+/// `disown [-a] [%jobid|pid ...]`. With no arguments, the current job is
+/// disowned, and `-a` disowns every job.
+fn do_disown(argv: &[String]) -> i32 {
+    let mut pids = Vec::new();
+    let mut status = 0;
+
+    if argv.len() == 1 {
+        match joblist()
+            .current_jid()
+            .and_then(|jid| joblist().jid2pid(jid))
+        {
+            Some(pid) => pids.push(pid),
+            None => {
+                complain("disown: current: no such job");
+                return 1;
+            }
+        }
+    } else if argv[1] == "-a" {
+        pids.extend(joblist().iter().map(|job| job.pid()));
+    } else {
+        for arg in &argv[1..] {
+            match lookup_job("disown", arg) {
+                Some(job) => pids.push(job.pid()),
+                None => status = 1,
+            }
+        }
+    }
+
+    for pid in pids {
+        if let Err(e) = joblist().deletejob(pid) {
+            ShellError::from(e).report();
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Print the complaint about an argument that isn't a variable name
+fn not_an_identifier(cmd: &str, name: &str) {
+    complain(format!("{}: `{}': not a valid identifier", cmd, name));
+}
+
+/// Export variables to the environment of commands run from now on:
+/// `export [-p] [NAME[=value] ...]`. With no names, lists the exported
+/// variables in a form that can be read back in.
+fn do_export(argv: &[String]) -> i32 {
+    let names = match argv.get(1).map(|x| &x[..]) {
+        None | Some("-p") if argv.len() <= 2 => {
+            for (name, var) in vars().iter().filter(|(_, var)| var.exported()) {
+                println!("export {}={}", name, quote(var.value()));
+            }
+            return 0;
+        }
+        _ => &argv[1..],
+    };
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.find('=') {
+            Some(eq) => (&arg[..eq], Some(arg[eq + 1..].to_string())),
+            None => (&arg[..], None),
+        };
+        if is_valid_name(name) {
+            vars().export(name, value);
+        } else {
+            not_an_identifier("export", arg);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Remove variables or functions from the shell: `unset [-v | -f] NAME ...`.
+/// Without a flag, a name that isn't a variable is taken to be a function.
+fn do_unset(argv: &[String]) -> i32 {
+    let (names, variables, functions_too) = match argv.get(1).map(|x| &x[..]) {
+        Some("-v") => (&argv[2..], true, false),
+        Some("-f") => (&argv[2..], false, true),
+        _ => (&argv[1..], true, true),
+    };
+    let mut status = 0;
+    for name in names {
+        if is_valid_name(name) {
+            if variables && vars().get(name).is_some() {
+                vars().unset(name);
+            } else if functions_too {
+                functions().remove(name);
+            }
+        } else {
+            not_an_identifier("unset", name);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Print the environment that commands would be run with: `env`
+fn do_env(argv: &[String]) -> i32 {
+    if argv.len() > 1 {
+        complain("env: usage: env (use NAME=value cmd to run a command with extra variables)");
+        return 1;
+    }
+    for (name, value) in vars().environ(&[]) {
+        println!("{}={}", name, value);
+    }
+    0
+}
+
+/// List every shell variable, exported or not: `set`. `set -- args ...`
+/// replaces the positional parameters instead, and `set -o name` and
+/// `set +o name` turn an option on and off (`set -o` lists them).
+fn do_set(argv: &[String]) -> i32 {
+    match argv.get(1).map(|x| &x[..]) {
+        None => {}
+        Some("--") => {
+            vars().set_params(argv[2..].to_vec());
+            return 0;
+        }
+        Some(flag @ "-o") | Some(flag @ "+o") => return set_options(flag == "-o", &argv[2..]),
+        Some(_) => {
+            complain("set: usage: set [-- args ...] [-o | +o [option ...]]");
+            return 1;
+        }
+    }
+    for (name, var) in vars().iter() {
+        println!("{}={}", name, quote(var.value()));
+    }
+    0
+}
+
+/// The options that `set -o` knows about
+const OPTIONS: &[&str] = &["failglob", "nullglob"];
+
+/// Get a handle to the option called `name`, if there is one
+fn option(name: &str) -> Option<&'static mut bool> {
+    let glob_options = vars().glob_options_mut();
+    match name {
+        "failglob" => Some(&mut glob_options.failglob),
+        "nullglob" => Some(&mut glob_options.nullglob),
+        _ => None,
+    }
+}
+
+/// Turn the named options on or off. With no names, `set -o` lists the options
+/// and `set +o` prints the commands that would restore them.
+fn set_options(on: bool, names: &[String]) -> i32 {
+    if names.is_empty() {
+        for name in OPTIONS {
+            let value = option(name).is_some_and(|x| *x);
+            if on {
+                println!("{:<15}{}", name, if value { "on" } else { "off" });
+            } else {
+                println!("set {}o {}", if value { '-' } else { '+' }, name);
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for name in names {
+        match option(name) {
+            Some(value) => *value = on,
+            None => {
+                complain(format!("set: {}: invalid option name", name));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Drop positional parameters, so that `$2` becomes `$1`: `shift [n]`
+fn do_shift(argv: &[String]) -> i32 {
+    let n = match argv.get(1).map(|x| x.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) if argv.len() == 2 => n,
+        _ => {
+            complain("shift: usage: shift [n]");
+            return 1;
+        }
+    };
+    if vars().shift(n) {
+        0
+    } else {
+        complain(format!("shift: {}: shift count out of range", n));
+        1
+    }
+}
+
+/// Run the commands in a file in the current shell: `source file [args ...]`,
+/// or `. file [args ...]`. Any args become the positional parameters while the
+/// file runs. Returns the status of the last command in the file.
+fn do_source(argv: &[String]) -> i32 {
+    let path = match argv.get(1) {
+        Some(path) => path,
+        None => {
+            complain(format!("{}: filename argument required", argv[0]));
+            return 2;
+        }
+    };
+    let script = match std::fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            let errno = nix::errno::from_i32(e.raw_os_error().unwrap_or(0));
+            complain(format!("{}: {}: {}", argv[0], path, errno.desc()));
+            return 1;
+        }
+    };
+
+    let saved_params = if argv.len() > 2 {
+        Some(vars().set_params(argv[2..].to_vec()))
+    } else {
+        None
+    };
+    let mut status = 0;
+    for command in split_script(&script) {
+        status = eval(&command).unwrap_or(status);
+    }
+    if let Some(params) = saved_params {
+        vars().set_params(params);
+    }
+    status
+}
+
+/// Change the working directory: `cd [dir | -]`. With no argument, goes to
+/// `$HOME`, and `cd -` goes back to `$OLDPWD`. Relative directories are looked
+/// up in `$CDPATH` first.
+fn do_cd(argv: &[String]) -> i32 {
+    if argv.len() > 2 {
+        complain("cd: too many arguments");
+        return 1;
+    }
+    let (dir, mut print) = match argv.get(1).map(|x| &x[..]) {
+        None => match vars().get("HOME") {
+            Some(home) => (home.to_string(), false),
+            None => {
+                complain("cd: HOME not set");
+                return 1;
+            }
+        },
+        Some("-") => match vars().get("OLDPWD") {
+            Some(oldpwd) => (oldpwd.to_string(), true),
+            None => {
+                complain("cd: OLDPWD not set");
+                return 1;
+            }
+        },
+        Some(dir) => (dir.to_string(), false),
+    };
+
+    let (target, from_cdpath) = search_cdpath(&dir);
+    print |= from_cdpath;
+    match change_dir(&target) {
+        Ok(()) => {
+            if print {
+                println!("{}", current_dir());
+            }
+            0
+        }
+        Err(reason) => {
+            complain(format!("cd: {}: {}", dir, reason));
+            1
+        }
+    }
+}
+
+/// Print the working directory: `pwd [-LP]`. `-P` resolves symlinks.
+fn do_pwd(argv: &[String]) -> i32 {
+    if argv.iter().skip(1).any(|x| x == "-P") {
+        match std::env::current_dir() {
+            Ok(dir) => println!("{}", dir.display()),
+            Err(e) => {
+                complain(format!("pwd: {}", e));
+                return 1;
+            }
+        }
+    } else {
+        println!("{}", current_dir());
+    }
+    0
+}
+
+/// Print the directory stack on one line, current directory first
+fn print_dir_stack() {
+    let mut line = tilde_abbrev(&current_dir());
+    for dir in dir_stack().iter().rev() {
+        line.push(' ');
+        line.push_str(&tilde_abbrev(dir));
+    }
+    println!("{}", line);
+}
+
+/// Save the current directory and change to another: `pushd [dir]`. With no
+/// argument, swaps the current directory with the top of the stack.
+fn do_pushd(argv: &[String]) -> i32 {
+    let old = current_dir();
+    let (dir, popped) = match argv.get(1) {
+        Some(dir) => (search_cdpath(dir).0, false),
+        None => match dir_stack().pop() {
+            Some(dir) => (dir, true),
+            None => {
+                complain("pushd: no other directory");
+                return 1;
+            }
+        },
+    };
+
+    match change_dir(&dir) {
+        Ok(()) => {
+            dir_stack().push(old);
+            print_dir_stack();
+            0
+        }
+        Err(reason) => {
+            complain(format!("pushd: {}: {}", dir, reason));
+            if popped {
+                dir_stack().push(dir);
+            }
+            1
+        }
+    }
+}
+
+/// Return to the directory on top of the stack: `popd`
+fn do_popd(_argv: &[String]) -> i32 {
+    let dir = match dir_stack().pop() {
+        Some(dir) => dir,
+        None => {
+            complain("popd: directory stack empty");
+            return 1;
+        }
+    };
+    match change_dir(&dir) {
+        Ok(()) => {
+            print_dir_stack();
+            0
+        }
+        Err(reason) => {
+            complain(format!("popd: {}: {}", dir, reason));
+            dir_stack().push(dir);
+            1
+        }
+    }
+}
+
+/// Show the directory stack: `dirs [-c | -v]`. `-c` clears the stack, and `-v`
+/// lists one directory per line along with its position.
+fn do_dirs(argv: &[String]) -> i32 {
+    match argv.get(1).map(|x| &x[..]) {
+        None => print_dir_stack(),
+        Some("-c") => dir_stack().clear(),
+        Some("-v") => {
+            println!(" 0  {}", tilde_abbrev(&current_dir()));
+            for (i, dir) in dir_stack().iter().rev().enumerate() {
+                println!("{:2}  {}", i + 1, tilde_abbrev(dir));
+            }
+        }
+        Some(_) => {
+            complain("dirs: usage: dirs [-c | -v]");
+            return 1;
+        }
+    }
+    0
+}
+
+/// Show the command history: `history [n]` lists the last n lines (all of them
+/// by default), and `history -c` forgets them.
+fn do_history(argv: &[String]) -> i32 {
+    let entries = history().entries();
+    let count = match argv.get(1).map(|x| &x[..]) {
+        None => entries.len(),
+        Some("-c") if argv.len() == 2 => {
+            history().clear();
+            return 0;
+        }
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if argv.len() == 2 => n.min(entries.len()),
+            _ => {
+                complain("history: usage: history [-c | n]");
+                return 1;
+            }
+        },
+    };
+    let first = entries.len() - count;
+    for (n, line) in entries.iter().enumerate().skip(first) {
+        println!("{:5}  {}", n + 1, line);
+    }
+    0
+}
+
+/// Define or show aliases: `alias [-p] [name[=value] ...]`. With no names,
+/// lists every alias in a form that can be read back in.
+fn do_alias(argv: &[String]) -> i32 {
+    let show = |name: &str, value: &str| println!("alias {}={}", name, quote(value));
+    let args = match argv.get(1).map(|x| &x[..]) {
+        Some("-p") => &argv[2..],
+        _ => &argv[1..],
+    };
+    if args.is_empty() {
+        for (name, value) in aliases().iter() {
+            show(name, value);
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match arg.find('=') {
+            Some(eq) if is_valid_alias_name(&arg[..eq]) => {
+                aliases().set(&arg[..eq], &arg[eq + 1..]);
+            }
+            Some(eq) => {
+                complain(format!("alias: `{}': invalid alias name", &arg[..eq]));
+                status = 1;
+            }
+            None => match aliases().get(arg) {
+                Some(value) => show(arg, value),
+                None => {
+                    complain(format!("alias: {}: not found", arg));
+                    status = 1;
+                }
+            },
+        }
+    }
+    status
+}
+
+/// Remove aliases: `unalias [-a] name ...`. `-a` removes all of them.
+fn do_unalias(argv: &[String]) -> i32 {
+    match argv.get(1).map(|x| &x[..]) {
+        Some("-a") if argv.len() == 2 => {
+            aliases().clear();
+            return 0;
+        }
+        None | Some("-a") => {
+            complain("unalias: usage: unalias [-a] name [name ...]");
+            return 2;
+        }
+        Some(_) => {}
+    }
+    let mut status = 0;
+    for name in &argv[1..] {
+        if !aliases().remove(name) {
+            complain(format!("unalias: {}: not found", name));
+            status = 1;
+        }
+    }
+    status
+}
+
+/// List functions: `declare -f [name ...]` shows their definitions, and
+/// `declare -F [name ...]` only their names. With no names, lists them all.
+fn do_declare(argv: &[String]) -> i32 {
+    let definitions = match argv.get(1).map(|x| &x[..]) {
+        Some("-f") => true,
+        Some("-F") => false,
+        _ => {
+            complain("declare: usage: declare -f | -F [name ...]");
+            return 2;
+        }
+    };
+    let show = |name: &str, text: &str| {
+        if definitions {
+            println!("{}", text);
+        } else {
+            println!("declare -f {}", name);
+        }
+    };
+    if argv.len() == 2 {
+        for (name, function) in functions().iter() {
+            show(name, &function.text);
+        }
+        return 0;
+    }
+
+    // Like bash, names that aren't functions just make the status 1
+    let mut status = 0;
+    for name in &argv[2..] {
+        match functions().get(name) {
+            Some(function) => show(name, &function.text),
+            None => status = 1,
+        }
+    }
+    status
+}
+
+/// Set, remove or show traps: `trap 'command' SIG ...` runs the command when
+/// one of the signals arrives (or as the shell exits, for EXIT), `trap '' SIG`
+/// ignores the signal, and `trap - SIG ...` removes the trap. `trap` or
+/// `trap -p [SIG ...]` shows the traps in a form that can be read back in.
+fn do_trap(argv: &[String]) -> i32 {
+    let show = |signal: i32| {
+        if let Some(command) = traps().get(signal) {
+            println!("trap -- {} {}", quote(command), trap_name(signal));
+        }
+    };
+    let mut args = &argv[1..];
+    if args.first().map(|x| &x[..]) == Some("--") {
+        args = &args[1..];
+    }
+
+    let (action, names) = match args.first().map(|x| &x[..]) {
+        None => {
+            for (signal, _) in traps().iter() {
+                show(signal);
+            }
+            return 0;
+        }
+        Some("-p") if args.len() == 1 => {
+            for (signal, _) in traps().iter() {
+                show(signal);
+            }
+            return 0;
+        }
+        Some(_) if args.len() == 1 => {
+            complain("trap: usage: trap [-p] [[command | -] signal ...]");
+            return 2;
+        }
+        Some(action) => (action, &args[1..]),
+    };
+
+    let mut status = 0;
+    for name in names {
+        let signal = match parse_trap(name) {
+            Some(signal) => signal,
+            None => {
+                complain(format!("trap: {}: invalid signal specification", name));
+                status = 1;
+                continue;
+            }
+        };
+        match action {
+            "-p" => show(signal),
+            "-" => reset_trap(signal),
+            command => set_trap(signal, command),
+        }
+    }
+    status
+}
+
+/// Show the CPU time used so far: `times`. The first line is the shell's own
+/// user and system time, and the second that of the jobs it has run.
+fn do_times(_argv: &[String]) -> i32 {
+    for times in &[CpuTimes::shell(), CpuTimes::children()] {
+        println!(
+            "{} {}",
+            format_duration(times.user),
+            format_duration(times.sys)
+        );
+    }
+    0
+}
+
+/// Show or set the resource limits of the jobs the shell runs:
+/// `ulimit [-t | -v | -n] [limit | unlimited]`, or `ulimit -a` to show all of
+/// them. `-t` is CPU seconds, `-v` virtual memory in kbytes, and `-n` open
+/// files. The shell itself isn't limited.
+fn do_ulimit(argv: &[String]) -> i32 {
+    let usage = || {
+        complain("ulimit: usage: ulimit [-a | -t | -v | -n] [limit | unlimited]");
+        1
+    };
+    let show = |index: usize| match get_limit(index) {
+        Some(value) => value.to_string(),
+        None => "unlimited".to_string(),
+    };
+
+    let (flag, value) = match argv.len() {
+        1 => ("-a", None),
+        2 if argv[1].starts_with('-') => (&argv[1][..], None),
+        3 => (&argv[1][..], Some(&argv[2])),
+        _ => return usage(),
+    };
+    if flag == "-a" && value.is_none() {
+        for (index, limit) in LIMITS.iter().enumerate() {
+            println!("{:<28}(-{}) {}", limit.description, limit.flag, show(index));
+        }
+        return 0;
+    }
+
+    let mut flag_chars = flag.chars();
+    let index = match (flag_chars.next(), flag_chars.next(), flag_chars.next()) {
+        (Some('-'), Some(c), None) => match find_limit(c) {
+            Some(index) => index,
+            None => return usage(),
+        },
+        _ => return usage(),
+    };
+    let value = match value.map(|x| &x[..]) {
+        None => {
+            println!("{}", show(index));
+            return 0;
+        }
+        Some("unlimited") => None,
+        Some(value) => match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                complain(format!("ulimit: {}: invalid number", value));
+                return 1;
+            }
+        },
+    };
+    match set_limit(index, value) {
+        Ok(()) => 0,
+        Err(reason) => {
+            complain(format!(
+                "ulimit: {}: cannot modify limit: {}",
+                LIMITS[index].description, reason
+            ));
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jobs_options() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+            JobsOptions::parse(&args)
+        };
+        assert_eq!(parse(&[]), Ok(JobsOptions::default()));
+        assert_eq!(parse(&["-l"]), Ok(JobsOptions::default()));
+        assert_eq!(
+            parse(&["-p", "-r"]),
+            Ok(JobsOptions {
+                pids_only: true,
+                running: true,
+                stopped: false,
+            })
+        );
+        assert_eq!(
+            parse(&["-lps"]),
+            Ok(JobsOptions {
+                pids_only: true,
+                running: false,
+                stopped: true,
+            })
+        );
+        assert_eq!(parse(&["-rx"]), Err(String::from("-x")));
+        assert_eq!(parse(&["-r", "p"]), Err(String::from("p")));
+        assert_eq!(parse(&["-"]), Err(String::from("-")));
+    }
+
+    #[test]
+    fn test_builtins_list() {
+        for name in BUILTINS {
+            assert!(is_builtin(name), "{}", name);
+        }
+        assert!(!is_builtin("ls"));
+    }
+}
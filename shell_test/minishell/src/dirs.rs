@@ -0,0 +1,173 @@
+/// The shell's working directory: changing it, keeping `$PWD` and `$OLDPWD` in
+/// sync, and the directory stack used by pushd, popd and dirs.
+/* Notes:
+  Like bash, the shell tracks the working directory logically: `cd ..` after
+  following a symlink goes back to where the user came from, not to the parent
+  of the symlink's target. `$PWD` holds the logical path, and `pwd -P` asks the
+  kernel for the physical one.
+*/
+use crate::vars::vars;
+use nix::errno::Errno;
+use std::path::{Component, Path, PathBuf};
+
+/// Directories saved by pushd, most recent last. The top of the stack as shown
+/// by dirs is always the current directory, which isn't stored here.
+static mut DIR_STACK: Vec<String> = Vec::new();
+
+pub fn dir_stack() -> &'static mut Vec<String> {
+    unsafe { &mut *std::ptr::addr_of_mut!(DIR_STACK) }
+}
+
+/// Make sure that `$PWD` names the current directory. An inherited `$PWD` is
+/// kept if it's still accurate, since it may go through symlinks.
+pub fn init() {
+    let physical = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let inherited = vars().get("PWD").map(PathBuf::from);
+    let accurate = inherited.as_ref().is_some_and(|pwd| {
+        pwd.is_absolute() && std::fs::canonicalize(pwd).ok().as_ref() == Some(&physical)
+    });
+    if !accurate {
+        vars().export("PWD", Some(physical.to_string_lossy().into_owned()));
+    }
+}
+
+/// The current directory as the shell sees it
+pub fn current_dir() -> String {
+    match vars().get("PWD") {
+        Some(pwd) => pwd.to_string(),
+        None => std::env::current_dir()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Resolve `.` and `..` in `path` without looking at the filesystem
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(part) => normalized.push(part),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+/// Abbreviate a leading `$HOME` to `~`, as dirs prints directories
+pub fn tilde_abbrev(dir: &str) -> String {
+    match vars().get("HOME") {
+        Some(home) if !home.is_empty() && home != "/" => match dir.strip_prefix(home) {
+            Some("") => "~".to_string(),
+            Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+            _ => dir.to_string(),
+        },
+        _ => dir.to_string(),
+    }
+}
+
+/// Change to `dir`, relative to the logical current directory, and update
+/// `$PWD` and `$OLDPWD`. On failure, returns the reason, e.g. "No such file
+/// or directory".
+pub fn change_dir(dir: &str) -> Result<(), &'static str> {
+    let old = current_dir();
+    let target = normalize(&Path::new(&old).join(dir));
+
+    // Fall back to the path as given if the logical one doesn't work out, e.g.
+    // if `..` wasn't a real parent because of a symlink that has since changed
+    let new = if nix::unistd::chdir(&target).is_ok() {
+        target.to_string_lossy().into_owned()
+    } else if nix::unistd::chdir(dir).is_ok() {
+        std::env::current_dir()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| dir.to_string())
+    } else {
+        return Err(Errno::last().desc());
+    };
+
+    vars().export("OLDPWD", Some(old));
+    vars().export("PWD", Some(new));
+    Ok(())
+}
+
+/// Find the directory that `cd dir` should change to, searching `$CDPATH` for
+/// relative names. Returns the directory, and whether it came from a `$CDPATH`
+/// entry (in which case cd prints it, like bash).
+pub fn search_cdpath(dir: &str) -> (String, bool) {
+    let relative = !(dir.starts_with('/')
+        || dir == "."
+        || dir == ".."
+        || dir.starts_with("./")
+        || dir.starts_with("../"));
+    let cdpath = match vars().get("CDPATH") {
+        Some(cdpath) if relative => cdpath.to_string(),
+        _ => return (dir.to_string(), false),
+    };
+
+    let base = PathBuf::from(current_dir());
+    for entry in cdpath.split(':') {
+        // An empty entry means the current directory
+        let candidate = if entry.is_empty() {
+            PathBuf::from(dir)
+        } else {
+            PathBuf::from(entry).join(dir)
+        };
+        if base.join(&candidate).is_dir() {
+            return (candidate.to_string_lossy().into_owned(), !entry.is_empty());
+        }
+    }
+    (dir.to_string(), false)
+}
+
+/// The directories in `$PATH`, in order. An empty entry means the current
+/// directory.
+pub fn path_dirs() -> Vec<PathBuf> {
+    vars()
+        .get("PATH")
+        .unwrap_or("")
+        .split(':')
+        .map(|dir| PathBuf::from(if dir.is_empty() { "." } else { dir }))
+        .collect()
+}
+
+/// Whether `path` is a file that someone may execute
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match std::fs::metadata(path) {
+        Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Find the program that a command name without a slash runs, by searching
+/// `$PATH`
+pub fn find_in_path(name: &str) -> Option<String> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    path_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("/a/b/../c/./d")),
+            PathBuf::from("/a/c/d")
+        );
+        assert_eq!(normalize(Path::new("/a/../..")), PathBuf::from("/"));
+        assert_eq!(normalize(Path::new("/a/b/")), PathBuf::from("/a/b"));
+    }
+}
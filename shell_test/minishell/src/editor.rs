@@ -0,0 +1,592 @@
+/// An emacs-style line editor for the interactive prompt, with history (see
+/// history.rs) and tab completion of command names and paths.
+/* Notes:
+  The editor is only used when stdin is a terminal. It puts the terminal in raw
+  mode while a line is being typed, and back into the modes it found before the
+  line is run, so the jobs the shell starts see the terminal as they always did.
+
+  ISIG is off in raw mode, so ^C and ^Z arrive here as characters rather than
+  as signals: ^C throws the line away, and ^Z is ignored.
+
+  Lines are assumed to fit on one row of the terminal. The editor redraws a
+  line by going back to the start of the row, and doesn't know about wrapping.
+*/
+use crate::aliases::aliases;
+use crate::builtins::BUILTINS;
+use crate::dirs::{is_executable, path_dirs};
+use crate::functions::functions;
+use crate::history::history;
+use crate::vars::vars;
+use nix::sys::termios::{
+    tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg, SpecialCharacterIndices,
+};
+use std::io::{self, BufRead, Write};
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+const TERMINAL_FD: RawFd = 0;
+
+/// Characters that end a word, as far as completion is concerned
+const WORD_BREAKS: &str = ";&|()<>";
+
+/// Characters that have to be escaped when completion inserts them
+const SPECIAL_CHARS: &str = " \t\\'\"$&;|()<>*?#`!{}[]";
+
+/// A key, or a combination of keys, typed at the terminal
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Ctrl(char), // The letter, e.g. Ctrl('a') for ^A
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Unknown,
+}
+
+/// Read a byte from the terminal, or None at EOF
+fn read_byte() -> Option<u8> {
+    let mut byte = [0u8];
+    loop {
+        match nix::unistd::read(TERMINAL_FD, &mut byte) {
+            Ok(1) => return Some(byte[0]),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Read the rest of an escape sequence, after the ESC
+fn read_escape() -> Option<Key> {
+    let key = match read_byte()? {
+        b'[' | b'O' => {
+            // A CSI sequence: parameters, then a final byte from @ to ~
+            let mut params = String::new();
+            let last = loop {
+                let byte = read_byte()?;
+                if (0x40..=0x7e).contains(&byte) {
+                    break byte;
+                }
+                params.push(byte as char);
+            };
+            match (last, &params[..]) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) | (b'~', "1") | (b'~', "7") => Key::Home,
+                (b'F', _) | (b'~', "4") | (b'~', "8") => Key::End,
+                (b'~', "3") => Key::Delete,
+                _ => Key::Unknown,
+            }
+        }
+        0x7f => Key::AltBackspace,
+        byte if byte.is_ascii_alphabetic() => Key::Alt(byte.to_ascii_lowercase() as char),
+        _ => Key::Unknown,
+    };
+    Some(key)
+}
+
+/// Read the next key from the terminal, or None at EOF
+fn read_key() -> Option<Key> {
+    let key = match read_byte()? {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape()?,
+        byte @ 1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        0 => Key::Unknown, // ^@, which can't be part of a command line
+        byte if byte < 0x80 => Key::Char(byte as char),
+        byte => {
+            // The first byte of a UTF-8 sequence says how many more follow
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|x| x.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    };
+    Some(key)
+}
+
+/// Write to the terminal. There's nothing useful to do if that fails.
+fn output(text: &str) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// The width of the terminal, in columns
+fn terminal_width() -> usize {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let ok = unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+/// Read a line from the terminal with the editor, showing `prompt` first.
+/// Returns None at EOF, i.e. ^D on an empty line. The line doesn't include a
+/// trailing newline.
+pub fn read_line(prompt: &str) -> Option<String> {
+    let saved = match tcgetattr(TERMINAL_FD) {
+        Ok(modes) => modes,
+        Err(_) => return read_plain_line(prompt),
+    };
+    let mut raw = saved.clone();
+    raw.input_flags
+        .remove(InputFlags::ICRNL | InputFlags::IXON | InputFlags::INLCR);
+    raw.local_flags
+        .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+    raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+    raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+    // TCSADRAIN rather than TCSAFLUSH, so that anything typed ahead is kept
+    if tcsetattr(TERMINAL_FD, SetArg::TCSADRAIN, &raw).is_err() {
+        return read_plain_line(prompt);
+    }
+
+    let line = LineState::new(prompt).edit();
+    let _ = tcsetattr(TERMINAL_FD, SetArg::TCSADRAIN, &saved);
+    line
+}
+
+/// Read a line without the editor, for when the terminal can't be put into raw
+/// mode
+fn read_plain_line(prompt: &str) -> Option<String> {
+    output(prompt);
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches('\n').to_string()),
+    }
+}
+
+/// The line being edited
+struct LineState<'a> {
+    prompt: &'a str,
+    buf: Vec<char>,
+    cursor: usize,
+    killed: String,         // The last text cut with ^K, ^U or ^W, for ^Y
+    history_index: usize,   // The history entry shown, or the length of the history
+    edited_line: Vec<char>, // The line being typed, while browsing the history
+    last_key: Option<Key>,
+}
+
+impl<'a> LineState<'a> {
+    fn new(prompt: &'a str) -> Self {
+        LineState {
+            prompt,
+            buf: Vec::new(),
+            cursor: 0,
+            killed: String::new(),
+            history_index: history().entries().len(),
+            edited_line: Vec::new(),
+            last_key: None,
+        }
+    }
+
+    /// Run the editor until the line is entered
+    fn edit(mut self) -> Option<String> {
+        output(self.prompt);
+        loop {
+            let key = read_key()?;
+            match key {
+                Key::Enter => {
+                    self.cursor = self.buf.len();
+                    self.refresh();
+                    output("\n");
+                    return Some(self.buf.iter().collect());
+                }
+                Key::Ctrl('d') if self.buf.is_empty() => {
+                    output("\n");
+                    return None;
+                }
+                Key::Ctrl('c') => {
+                    output("^C\n");
+                    self.buf.clear();
+                    self.cursor = 0;
+                    self.history_index = history().entries().len();
+                    output(self.prompt);
+                }
+                Key::Ctrl('l') => {
+                    output("\x1b[H\x1b[2J");
+                    output(self.prompt);
+                    self.refresh();
+                }
+                Key::Tab => self.complete(),
+                key => {
+                    self.edit_key(key);
+                    self.refresh();
+                }
+            }
+            self.last_key = Some(key);
+        }
+    }
+
+    /// Handle a key that changes the line or moves around in it
+    fn edit_key(&mut self, key: Key) {
+        let len = self.buf.len();
+        match key {
+            Key::Char(c) => {
+                self.buf.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace | Key::Ctrl('h') if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buf.remove(self.cursor);
+            }
+            Key::Delete | Key::Ctrl('d') if self.cursor < len => {
+                self.buf.remove(self.cursor);
+            }
+            Key::Left | Key::Ctrl('b') if self.cursor > 0 => self.cursor -= 1,
+            Key::Right | Key::Ctrl('f') if self.cursor < len => self.cursor += 1,
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = len,
+            Key::Alt('b') => self.cursor = self.word_start(),
+            Key::Alt('f') => self.cursor = self.word_end(),
+            Key::Ctrl('k') => self.kill(self.cursor, len),
+            Key::Ctrl('u') => self.kill(0, self.cursor),
+            Key::Ctrl('w') | Key::AltBackspace => self.kill(self.word_start(), self.cursor),
+            Key::Alt('d') => self.kill(self.cursor, self.word_end()),
+            Key::Ctrl('y') => {
+                let killed: Vec<char> = self.killed.chars().collect();
+                self.buf
+                    .splice(self.cursor..self.cursor, killed.iter().cloned());
+                self.cursor += killed.len();
+            }
+            Key::Ctrl('t') if self.cursor > 0 && len >= 2 => {
+                // Swap the characters before the cursor, moving it along
+                let at = self.cursor.min(len - 1);
+                self.buf.swap(at - 1, at);
+                self.cursor = at + 1;
+            }
+            Key::Up | Key::Ctrl('p') => self.history_move(-1),
+            Key::Down | Key::Ctrl('n') => self.history_move(1),
+            _ => output("\x07"), // Ring the bell for anything else
+        }
+    }
+
+    /// Redraw the line, and put the cursor back where it belongs
+    fn refresh(&self) {
+        // Only the last line of a multi-line prompt needs redrawing
+        let prompt = self.prompt.rsplit('\n').next().unwrap_or("");
+        let line: String = self.buf.iter().collect();
+        let mut text = format!("\r{}{}\x1b[K", prompt, line);
+        let back = self.buf.len() - self.cursor;
+        if back > 0 {
+            text.push_str(&format!("\x1b[{}D", back));
+        }
+        output(&text);
+    }
+
+    /// The start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.buf[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.buf[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// The end of the word after the cursor
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.buf.len() && !self.buf[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.buf.len() && self.buf[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    /// Cut buf[start..end], saving it for ^Y
+    fn kill(&mut self, start: usize, end: usize) {
+        self.killed = self.buf.drain(start..end).collect();
+        self.cursor = start;
+    }
+
+    /// Show an older (-1) or newer (1) line of history in place of this one
+    fn history_move(&mut self, direction: isize) {
+        let entries = history().entries();
+        let index = match direction {
+            -1 if self.history_index > 0 => self.history_index - 1,
+            1 if self.history_index < entries.len() => self.history_index + 1,
+            _ => return output("\x07"),
+        };
+        if self.history_index == entries.len() {
+            self.edited_line = self.buf.clone();
+        }
+        self.buf = match entries.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => self.edited_line.clone(),
+        };
+        self.history_index = index;
+        self.cursor = self.buf.len();
+    }
+
+    /// Complete the word before the cursor. The common prefix of the matches is
+    /// filled in, and a second tab lists them if that didn't get anywhere.
+    fn complete(&mut self) {
+        let word = current_word(&self.buf[..self.cursor]);
+        let matches = completions(&word);
+        let common = common_prefix(&matches);
+        let typed = word.text.chars().count();
+
+        if common.chars().count() > typed || (matches.len() == 1 && !common.ends_with('/')) {
+            let mut insert = escape(&common.chars().skip(typed).collect::<String>());
+            if matches.len() == 1 && !common.ends_with('/') {
+                insert.push(' ');
+            }
+            let insert: Vec<char> = insert.chars().collect();
+            self.buf
+                .splice(self.cursor..self.cursor, insert.iter().cloned());
+            self.cursor += insert.len();
+            self.refresh();
+        } else if matches.len() > 1 && self.last_key == Some(Key::Tab) {
+            // List the names without the directory part that they all share
+            let dir_len = word.text.rfind('/').map_or(0, |n| n + 1);
+            let names: Vec<&str> = matches.iter().map(|x| &x[dir_len..]).collect();
+            output("\n");
+            output(&columns(&names, terminal_width()));
+            output(self.prompt);
+            self.refresh();
+        } else {
+            output("\x07");
+        }
+    }
+}
+
+/// The word being completed, found by going back from the cursor
+#[derive(Debug, PartialEq, Eq)]
+struct Word {
+    text: String,           // The word, with backslash escapes removed
+    command_position: bool, // Whether it's the name of a command
+}
+
+fn current_word(before: &[char]) -> Word {
+    let mut start = before.len();
+    while start > 0 {
+        let c = before[start - 1];
+        let escaped = start > 1 && before[start - 2] == '\\';
+        if (c.is_whitespace() || WORD_BREAKS.contains(c)) && !escaped {
+            break;
+        }
+        start -= 1;
+    }
+
+    let mut text = String::new();
+    let mut chars = before[start..].iter();
+    while let Some(&c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(c),
+        }
+    }
+
+    let rest: String = before[..start].iter().collect();
+    let command_position = match rest.trim_end().chars().last() {
+        None => true,
+        Some(c) => WORD_BREAKS.contains(c) && c != '<' && c != '>',
+    };
+    Word {
+        text,
+        command_position,
+    }
+}
+
+/// Everything that could complete `word`, sorted. Directories end with `/`.
+fn completions(word: &Word) -> Vec<String> {
+    let mut matches = if word.command_position && !word.text.contains('/') {
+        command_completions(&word.text)
+    } else {
+        path_completions(&word.text, word.command_position)
+    };
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// Builtins, aliases, functions and programs in `$PATH` whose names start with
+/// `prefix`
+fn command_completions(prefix: &str) -> Vec<String> {
+    let mut matches: Vec<String> = BUILTINS
+        .iter()
+        .copied()
+        .chain(aliases().iter().map(|(name, _)| &name[..]))
+        .chain(functions().iter().map(|(name, _)| &name[..]))
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    for dir in path_dirs() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                matches.push(name);
+            }
+        }
+    }
+    matches
+}
+
+/// Paths that start with `prefix`. For a command, only directories and
+/// programs are of interest.
+fn path_completions(prefix: &str, programs_only: bool) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(n) => (&prefix[..n + 1], &prefix[n + 1..]),
+        None => ("", prefix),
+    };
+    let search_dir = if dir.is_empty() {
+        PathBuf::from(".")
+    } else if let Some(rest) = dir.strip_prefix("~/") {
+        PathBuf::from(vars().get("HOME").unwrap_or("")).join(rest)
+    } else {
+        PathBuf::from(dir)
+    };
+
+    let entries = match std::fs::read_dir(&search_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut matches = Vec::new();
+    for entry in entries.flatten() {
+        let entry_name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files only show up when asked for
+        if !entry_name.starts_with(name) || (entry_name.starts_with('.') && !name.starts_with('.'))
+        {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            matches.push(format!("{}{}/", dir, entry_name));
+        } else if !programs_only || is_executable(&path) {
+            matches.push(format!("{}{}", dir, entry_name));
+        }
+    }
+    matches
+}
+
+/// The longest prefix shared by all the `words`
+fn common_prefix(words: &[String]) -> String {
+    let first = match words.first() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(len);
+    }
+    first[..len].to_string()
+}
+
+/// Backslash-escape the characters in `text` that the shell would treat
+/// specially
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if SPECIAL_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Lay `names` out in columns, like ls does, to fit in `width` columns
+fn columns(names: &[&str], width: usize) -> String {
+    let column_width = names.iter().map(|x| x.chars().count()).max().unwrap_or(0) + 2;
+    let per_row = (width / column_width).max(1);
+    let mut text = String::new();
+    for row in names.chunks(per_row) {
+        for name in row {
+            text.push_str(&format!("{:width$}", name, width = column_width));
+        }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(before: &str) -> Word {
+        current_word(&before.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn test_current_word() {
+        let command = |text: &str| Word {
+            text: text.to_string(),
+            command_position: true,
+        };
+        let argument = |text: &str| Word {
+            text: text.to_string(),
+            command_position: false,
+        };
+        assert_eq!(word(""), command(""));
+        assert_eq!(word("  ec"), command("ec"));
+        assert_eq!(word("echo src/ma"), argument("src/ma"));
+        assert_eq!(word("echo a\\ b"), argument("a b"));
+        assert_eq!(word("cd dir && ma"), command("ma"));
+        assert_eq!(word("(cd dir;./ru"), command("./ru"));
+        assert_eq!(word("cat <fi"), argument("fi"));
+        assert_eq!(word("echo "), argument(""));
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let words = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&words(&["pushd", "pwd"])), "p");
+        assert_eq!(common_prefix(&words(&["src/", "src/main.rs"])), "src/");
+        assert_eq!(common_prefix(&words(&["héllo", "hélp"])), "hél");
+        assert_eq!(common_prefix(&words(&["jobs"])), "jobs");
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn test_escape_and_columns() {
+        assert_eq!(escape("it's a (file)"), "it\\'s\\ a\\ \\(file\\)");
+        assert_eq!(columns(&["a", "bb", "ccc", "d"], 16), "a    bb   ccc\nd\n");
+    }
+}
@@ -1 +1,132 @@
-/// What could possibly go wrong?
\ No newline at end of file
+/// What could possibly go wrong? The errors that the shell reports on its way
+/// back to the prompt (or on to the next line of a script): lines that don't
+/// parse, programs that can't be run, a full joblist, failed syscalls,
+/// runaway function calls and builtins' complaints.
+/* Notes:
+  Every error is reported on stderr. The traces still see them, since main()
+  points the shell's stderr at its stdout before anything else, as mshref's
+  driver expects. Builtins word their complaints the way mshref and bash do,
+  and return their own statuses for them, e.g. 2 for a usage error.
+
+  The signal handlers can't use any of this: they mustn't allocate, so they
+  quietly ignore anything that fails (e.g. signalling a job that has just
+  exited) instead.
+*/
+use crate::functions::MAX_DEPTH;
+use crate::jobs::JobListError;
+use crate::util::ParseFailReason;
+use nix::errno::Errno;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShellError {
+    Parse(ParseFailReason),   // A line that couldn't be parsed or expanded
+    CommandNotFound(String),  // A program that execve couldn't run
+    Job(JobListError),        // A joblist operation that failed
+    Sys(&'static str, Errno), // A failed syscall, and what it was for
+    TooDeep(String),          // A function call nested MAX_DEPTH deep
+    Builtin(String),          // A builtin's complaint, as it words it
+}
+
+impl ShellError {
+    /// The exit status that a command which failed this way gets, as in bash
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Parse(ParseFailReason::NoMatch(_)) => 1,
+            ShellError::Parse(_) => 2,
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::Job(_)
+            | ShellError::Sys(..)
+            | ShellError::TooDeep(_)
+            | ShellError::Builtin(_) => 1,
+        }
+    }
+
+    /// Tell the user about the error
+    pub fn report(&self) {
+        eprintln!("{}", self);
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Parse(reason @ ParseFailReason::NoMatch(_)) => write!(f, "{}", reason),
+            ShellError::Parse(reason) => write!(f, "syntax error: {}", reason),
+            ShellError::CommandNotFound(name) => write!(f, "{}: Command not found", name),
+            ShellError::Job(e) => write!(f, "{}", e),
+            ShellError::Sys(what, errno) => write!(f, "{}: {}", what, errno.desc()),
+            ShellError::TooDeep(name) => write!(
+                f,
+                "{}: maximum function nesting level exceeded ({})",
+                name, MAX_DEPTH
+            ),
+            ShellError::Builtin(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ParseFailReason> for ShellError {
+    fn from(reason: ParseFailReason) -> Self {
+        ShellError::Parse(reason)
+    }
+}
+
+impl From<JobListError> for ShellError {
+    fn from(e: JobListError) -> Self {
+        ShellError::Job(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ShellError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let cases = vec![
+            (
+                ShellError::from(ParseFailReason::Unmatched('\'')),
+                "syntax error: unmatched `''",
+                2,
+            ),
+            (
+                ShellError::from(ParseFailReason::Invalid(')')),
+                "syntax error: unexpected `)'",
+                2,
+            ),
+            (
+                ShellError::from(ParseFailReason::NoMatch("*.c".to_string())),
+                "no match: *.c",
+                1,
+            ),
+            (
+                ShellError::CommandNotFound("./bogus".to_string()),
+                "./bogus: Command not found",
+                127,
+            ),
+            (
+                ShellError::from(JobListError::TooManyJobs),
+                "too many jobs",
+                1,
+            ),
+            (ShellError::Sys("fork", Errno::EAGAIN), "fork: Try again", 1),
+            (
+                ShellError::TooDeep("f".to_string()),
+                "f: maximum function nesting level exceeded (200)",
+                1,
+            ),
+            (
+                ShellError::Builtin("cd: HOME not set".to_string()),
+                "cd: HOME not set",
+                1,
+            ),
+        ];
+        for (error, message, status) in cases {
+            assert_eq!(error.to_string(), message);
+            assert_eq!(error.status(), status);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+/// Shell functions, defined with `name() { ...; }`. A function runs in the
+/// shell process itself, like a builtin, with its arguments as the positional
+/// parameters while it runs.
+/* Notes:
+  A function is looked up before the builtins, so a function can wrap one of
+  them. Calls are counted, so that a function that calls itself forever gets an
+  error rather than overflowing the shell's stack.
+*/
+use crate::ast::Command;
+use std::collections::BTreeMap;
+
+/// How deeply functions can call each other
+pub const MAX_DEPTH: usize = 200;
+
+/// A function definition: the command it runs, which is a `{ ... }` group or a
+/// `( ... )` subshell, and the text it was defined with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub body: Command,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Functions {
+    functions: BTreeMap<String, Function>,
+    depth: usize, // How many calls are running right now
+}
+
+impl Functions {
+    pub const fn new() -> Self {
+        Functions {
+            functions: BTreeMap::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// Define a function, replacing any earlier one of the same name
+    pub fn define(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    /// Remove a function, returning false if there wasn't one
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.functions.remove(name).is_some()
+    }
+
+    /// The functions, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Function)> {
+        self.functions.iter()
+    }
+
+    /// Note the start of a call. Returns false, without counting it, if the
+    /// calls are already MAX_DEPTH deep.
+    pub fn enter(&mut self) -> bool {
+        if self.depth >= MAX_DEPTH {
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+
+    /// Note the end of a call that enter() allowed
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+static mut FUNCTIONS: Functions = Functions::new();
+
+/// Get a handle to the shell's functions
+pub fn functions() -> &'static mut Functions {
+    unsafe { &mut *std::ptr::addr_of_mut!(FUNCTIONS) }
+}
@@ -0,0 +1,476 @@
+/// Pathname and brace expansion. Unquoted `*`, `?` and `[...]` in a word make
+/// it a pattern that expands to the paths it matches, sorted, with `**`
+/// matching any number of directories. Brace expansion turns `a{b,c}` into `ab`
+/// and `ac`, and `{1..3}` into `1 2 3`, before anything else is expanded.
+/* Notes:
+  Patterns come from util::Expander, which escapes every quoted character with
+  a backslash, so `'*'.rs` is the pattern `\*.rs` and only matches a file called
+  `*.rs`. That's also why has_magic() is what decides whether a word is a
+  pattern at all.
+
+  Like bash, a `*`, `?` or `[` never matches the `.` at the start of a name, so
+  hidden files only show up for patterns like `.*`, and `**` doesn't descend
+  into hidden directories or follow symlinks to directories.
+*/
+use crate::util::{skip_braces, skip_parens, skip_quoted};
+use std::fs;
+
+/// The options that change what happens to a pattern that matches nothing.
+/// Without either, the pattern is left as it is.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlobOptions {
+    pub nullglob: bool, // Remove the word
+    pub failglob: bool, // Fail the command, which takes precedence
+}
+
+/// Parse a `[...]` bracket expression starting at p[i], just past the `[`.
+/// Returns whether `c` is in it and the index just past the `]`, or None if
+/// the bracket is never closed, in which case the `[` is an ordinary character.
+fn bracket(p: &[char], mut i: usize, c: char) -> Option<(bool, usize)> {
+    let negate = matches!(p.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let start = i;
+    let mut matched = false;
+    loop {
+        let ch = *p.get(i)?;
+        if ch == ']' && i > start {
+            return Some((matched != negate, i + 1));
+        }
+        let (low, next) = match ch {
+            '\\' => (*p.get(i + 1)?, i + 2),
+            _ => (ch, i + 1),
+        };
+        match (p.get(next), p.get(next + 1)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                let (high, after) = match high {
+                    '\\' => (*p.get(next + 2)?, next + 3),
+                    _ => (high, next + 2),
+                };
+                matched |= low <= c && c <= high;
+                i = after;
+            }
+            _ => {
+                matched |= low == c;
+                i = next;
+            }
+        }
+    }
+}
+
+/// If the part of the pattern at p[i] (anything but a `*`) matches `c`, returns
+/// the index of the rest of the pattern
+fn match_char(p: &[char], i: usize, c: char) -> Option<usize> {
+    match p[i] {
+        '?' => Some(i + 1),
+        '[' => match bracket(p, i + 1, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            None if c == '[' => Some(i + 1),
+            None => None,
+        },
+        '\\' if i + 1 < p.len() => Some(i + 2).filter(|_| p[i + 1] == c),
+        literal => Some(i + 1).filter(|_| literal == c),
+    }
+}
+
+/// Whether a file name matches a pattern. A leading `.` in the name has to be
+/// matched by a `.` in the pattern.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    if n.first() == Some(&'.') && !(pattern.starts_with('.') || pattern.starts_with("\\.")) {
+        return false;
+    }
+
+    // The usual backtracking: when something fails to match, let the last `*`
+    // swallow one more character and try again from there
+    let (mut pi, mut ni) = (0, 0);
+    let mut star = None;
+    while ni < n.len() {
+        if pi < p.len() && p[pi] == '*' {
+            pi += 1;
+            star = Some((pi, ni));
+            continue;
+        }
+        if pi < p.len() {
+            if let Some(next) = match_char(&p, pi, n[ni]) {
+                pi = next;
+                ni += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_pi, star_ni)) => {
+                pi = star_pi;
+                ni = star_ni + 1;
+                star = Some((star_pi, ni));
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Whether a pattern has anything unescaped that would match more than itself
+pub fn has_magic(pattern: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < p.len() {
+        match p[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if bracket(&p, i + 1, '\0').is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Remove the backslashes that escape characters in a pattern
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// Append a name to a path built up by walk(), where "" is the current directory
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else if path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(if path.is_empty() { "." } else { path }).is_ok_and(|x| x.is_dir())
+}
+
+/// The names in a directory, hidden ones included
+fn list_dir(path: &str) -> Vec<String> {
+    match fs::read_dir(if path.is_empty() { "." } else { path }) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str().map(|x| x.to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// `path` and every directory under it, for `**`
+fn descendant_dirs(path: &str, dirs: &mut Vec<String>) {
+    dirs.push(path.to_string());
+    for name in list_dir(path) {
+        let child = join(path, &name);
+        let real_dir = fs::symlink_metadata(&child).is_ok_and(|x| x.is_dir());
+        if !name.starts_with('.') && real_dir {
+            descendant_dirs(&child, dirs);
+        }
+    }
+}
+
+/// Find the paths under `path` that match the rest of a pattern, one path
+/// component at a time
+fn walk(path: String, components: &[&str], found: &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => return found.push(path),
+    };
+
+    if component.is_empty() {
+        // A trailing or doubled slash, which only a directory can have
+        if is_dir(&path) {
+            walk(format!("{}/", path), rest, found);
+        }
+    } else if *component == "**" {
+        let mut dirs = Vec::new();
+        descendant_dirs(&path, &mut dirs);
+        for dir in dirs {
+            if rest.is_empty() {
+                // Everything in every directory
+                for name in list_dir(&dir).iter().filter(|x| !x.starts_with('.')) {
+                    found.push(join(&dir, name));
+                }
+            } else {
+                walk(dir, rest, found);
+            }
+        }
+    } else if !has_magic(component) {
+        let next = join(&path, &unescape(component));
+        if rest.is_empty() {
+            if fs::symlink_metadata(&next).is_ok() {
+                found.push(next);
+            }
+        } else if is_dir(&next) {
+            walk(next, rest, found);
+        }
+    } else {
+        for name in list_dir(&path) {
+            if !matches(component, &name) {
+                continue;
+            }
+            let next = join(&path, &name);
+            if rest.is_empty() {
+                found.push(next);
+            } else if is_dir(&next) {
+                walk(next, rest, found);
+            }
+        }
+    }
+}
+
+/// The paths that a pattern matches, sorted
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (root, relative) = match pattern.strip_prefix('/') {
+        Some(relative) => ("/".to_string(), relative),
+        None => (String::new(), pattern),
+    };
+    let components: Vec<&str> = relative.split('/').collect();
+    let mut found = Vec::new();
+    walk(root, &components, &mut found);
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// The items of a `{a..b}` or `{a..b..step}` sequence, of numbers or of single
+/// letters, or None if `text` isn't one
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let (start, end, step) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?.checked_abs()?),
+        _ => return None,
+    };
+    let step = step.max(1);
+
+    let (first, last, letters) = match (start.parse::<i64>(), end.parse::<i64>()) {
+        (Ok(first), Ok(last)) => (first, last, false),
+        _ => {
+            let letter = |x: &str| match x.as_bytes() {
+                [c] if c.is_ascii_alphabetic() => Some(i64::from(*c)),
+                _ => None,
+            };
+            (letter(start)?, letter(end)?, true)
+        }
+    };
+    // Like bash, {01..10} pads the numbers to the same width
+    let padded =
+        |x: &str| x.trim_start_matches('-').len() > 1 && x.trim_start_matches('-').starts_with('0');
+    let width = if padded(start) || padded(end) {
+        start.len().max(end.len())
+    } else {
+        0
+    };
+
+    let mut items = Vec::new();
+    let mut n = first;
+    while (first <= last && n <= last) || (first > last && n >= last) {
+        items.push(if letters {
+            (n as u8 as char).to_string()
+        } else {
+            format!("{:0width$}", n, width = width)
+        });
+        n = match if first <= last {
+            n.checked_add(step)
+        } else {
+            n.checked_sub(step)
+        } {
+            Some(n) => n,
+            None => break,
+        };
+    }
+    Some(items)
+}
+
+/// If the `{` at chars[open] starts a brace expansion, returns the words it
+/// expands to
+fn expand_braces_at(chars: &[char], open: usize) -> Option<Vec<String>> {
+    // Find the matching `}`, and the commas that aren't in nested braces
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = open + 1;
+    let close = loop {
+        match *chars.get(i)? {
+            '\\' => i += 1,
+            c @ '\'' | c @ '"' => i = skip_quoted(chars, i + 1, c).ok()?,
+            '$' if chars.get(i + 1) == Some(&'{') => i = skip_braces(chars, i + 2).ok()?,
+            '$' | '<' if chars.get(i + 1) == Some(&'(') => i = skip_parens(chars, i + 2).ok()?,
+            '{' => depth += 1,
+            '}' if depth == 0 => break i,
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    };
+
+    let items: Vec<String> = if commas.is_empty() {
+        sequence(&chars[open + 1..close].iter().collect::<String>())?
+    } else {
+        let mut starts = vec![open + 1];
+        starts.extend(commas.iter().map(|&comma| comma + 1));
+        let mut ends = commas;
+        ends.push(close);
+        starts
+            .into_iter()
+            .zip(ends)
+            .map(|(start, end)| chars[start..end].iter().collect())
+            .collect()
+    };
+
+    let prefix: String = chars[..open].iter().collect();
+    let suffix: String = chars[close + 1..].iter().collect();
+    Some(
+        items
+            .iter()
+            .flat_map(|item| brace_expand(&format!("{}{}{}", prefix, item, suffix)))
+            .collect(),
+    )
+}
+
+/// Brace expansion of a word as it was typed. Braces that are quoted, belong to
+/// a `${...}`, are inside a substitution (whose command expands them itself),
+/// or hold neither a `,` nor a sequence (like `{}`) are left alone.
+pub fn brace_expand(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c @ '\'' | c @ '"' => match skip_quoted(&chars, i + 1, c) {
+                Ok(close) => i = close,
+                Err(_) => break,
+            },
+            '$' if chars.get(i + 1) == Some(&'{') => match skip_braces(&chars, i + 2) {
+                Ok(close) => i = close,
+                Err(_) => break,
+            },
+            '$' | '<' if chars.get(i + 1) == Some(&'(') => match skip_parens(&chars, i + 2) {
+                Ok(close) => i = close,
+                Err(_) => break,
+            },
+            '{' => {
+                if let Some(words) = expand_braces_at(&chars, i) {
+                    return words;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![word.to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rs.orig"));
+        assert!(matches("m?in*", "main.rs"));
+        assert!(matches("*a*b*c", "xxaybbzc"));
+        assert!(matches("[a-c]x[!0-9]", "bxy"));
+        assert!(!matches("[a-c]x[!0-9]", "bx7"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[^a]", "b"));
+        assert!(matches("a[", "a["));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(matches("", ""));
+        assert!(!matches("?", ""));
+        // Hidden files need an explicit dot
+        assert!(!matches("*", ".hidden"));
+        assert!(!matches("?hidden", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    #[test]
+    fn test_has_magic() {
+        assert!(has_magic("*.rs"));
+        assert!(has_magic("a?"));
+        assert!(has_magic("[ab]"));
+        assert!(!has_magic("["));
+        assert!(!has_magic("\\*.rs"));
+        assert!(!has_magic("plain/path"));
+        assert_eq!(unescape("\\*a\\\\b"), "*a\\b");
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = std::env::temp_dir().join(format!("rustymsh_glob.{}", std::process::id()));
+        for file in &[
+            "a.rs",
+            "b.rs",
+            "c.txt",
+            ".hidden.rs",
+            "src/d.rs",
+            "src/deep/e.rs",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let base = dir.to_str().unwrap();
+        let glob = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", base, pattern))
+                .iter()
+                .map(|x| x[base.len() + 1..].to_string())
+                .collect()
+        };
+
+        assert_eq!(glob("*.rs"), vec!["a.rs", "b.rs"]);
+        assert_eq!(glob("[ac].*"), vec!["a.rs", "c.txt"]);
+        assert_eq!(glob("*/"), vec!["src/"]);
+        assert_eq!(glob("s*/d.rs"), vec!["src/d.rs"]);
+        assert_eq!(
+            glob("**/*.rs"),
+            vec!["a.rs", "b.rs", "src/d.rs", "src/deep/e.rs"]
+        );
+        assert_eq!(
+            glob("src/**"),
+            vec!["src/d.rs", "src/deep", "src/deep/e.rs"]
+        );
+        assert_eq!(glob(".*"), vec![".hidden.rs"]);
+        assert!(glob("*.py").is_empty());
+        assert!(glob("nope/*").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_brace_expand() {
+        let expand = |word| brace_expand(word);
+        assert_eq!(expand("src/{a,b}.c"), vec!["src/a.c", "src/b.c"]);
+        assert_eq!(expand("{a,b{1,2}}x"), vec!["ax", "b1x", "b2x"]);
+        assert_eq!(expand("{a,b}{c,d}"), vec!["ac", "ad", "bc", "bd"]);
+        assert_eq!(expand("x{,y}"), vec!["x", "xy"]);
+        assert_eq!(expand("{1..3}"), vec!["1", "2", "3"]);
+        assert_eq!(expand("{3..-1..2}"), vec!["3", "1", "-1"]);
+        assert_eq!(expand("{08..10}"), vec!["08", "09", "10"]);
+        assert_eq!(expand("{c..a}"), vec!["c", "b", "a"]);
+        // Things that aren't brace expansions
+        for word in &[
+            "{}", "{a}", "{a,b", "'{a,b}'", "\\{a,b}", "${x,y}", "{1..}", "{a..bc}",
+        ] {
+            assert_eq!(expand(word), vec![word.to_string()], "{}", word);
+        }
+        for word in &["$(echo {a,b})", "<(echo {a,b})"] {
+            assert_eq!(expand(word), vec![word.to_string()], "{}", word);
+        }
+        assert_eq!(expand("\"{a,b}\"{c,d}"), vec!["\"{a,b}\"c", "\"{a,b}\"d"]);
+    }
+}
@@ -0,0 +1,240 @@
+/// The command history of the interactive shell: the lines the user has typed,
+/// kept in `$HISTFILE` (by default `~/.rustymsh_history`) between sessions, and
+/// the `!!`, `!n`, `!-n` and `!prefix` expansions that refer back to them.
+/* Notes:
+  History is only kept when the line editor is in use, i.e. when stdin is a
+  terminal. Scripts, -c commands and the trace files never see it, so a `!` in
+  them is just a `!`.
+
+  Each line is appended to the history file as soon as it's entered, so that
+  nothing is lost if the shell is killed, and so that there's no need to save
+  anything on the way out.
+*/
+use crate::vars::vars;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// How many lines of history are kept, in memory and in the file
+const HISTORY_SIZE: usize = 1000;
+
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        History {
+            entries: Vec::new(),
+            file: None,
+        }
+    }
+
+    /// Load the history saved in `file`, and append new lines to it from now on
+    pub fn load(&mut self, file: PathBuf) {
+        if let Ok(text) = std::fs::read_to_string(&file) {
+            self.entries = text.lines().map(|x| x.to_string()).collect();
+            if self.entries.len() > HISTORY_SIZE {
+                self.entries.drain(..self.entries.len() - HISTORY_SIZE);
+                // Don't let the file grow without bound either
+                let mut text = self.entries.join("\n");
+                text.push('\n');
+                let _ = std::fs::write(&file, text);
+            }
+        }
+        self.file = Some(file);
+    }
+
+    /// The lines in the history, oldest first. `!n` refers to entries()[n - 1].
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Remember a line the user entered. Blank lines, and lines that repeat the
+    /// one before, are skipped.
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() || self.entries.last().map(|x| &x[..]) == Some(line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+
+        // History is a convenience, so failing to save it isn't worth a complaint
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(file) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    /// Forget the history of this session, like `history -c`. The file is left
+    /// alone.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Expand the history references in `line`. Returns None if there weren't
+    /// any, or the event that couldn't be found, e.g. `!42`.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut expanded = String::new();
+        let mut changed = false;
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                // A `'` between double quotes is just a character, but `!`
+                // is still expanded there, as in bash
+                '\'' if !in_double => in_single = !in_single,
+                '"' if !in_single => in_double = !in_double,
+                '\\' if !in_single => {
+                    expanded.push(c);
+                    if let Some(&next) = chars.get(i + 1) {
+                        expanded.push(next);
+                    }
+                    i += 2;
+                    continue;
+                }
+                '!' if !in_single => {
+                    if let Some((entry, next)) = self.event(&chars, i + 1)? {
+                        expanded.push_str(entry);
+                        changed = true;
+                        i = next;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expanded.push(c);
+            i += 1;
+        }
+        Ok(if changed { Some(expanded) } else { None })
+    }
+
+    /// Look up the event designator starting at chars[i], just past a `!`.
+    /// Returns the entry and the index just past the designator, or None if
+    /// the `!` doesn't start one (e.g. `! cmd` or `a!=b`).
+    fn event(&self, chars: &[char], i: usize) -> Result<Option<(&str, usize)>, String> {
+        let end = chars[i..]
+            .iter()
+            .position(|&c| c.is_whitespace() || "!;&|()<>'\"=".contains(c))
+            .map_or(chars.len(), |n| i + n);
+        let (designator, next) = match chars.get(i) {
+            Some('!') => ("!".to_string(), i + 1),
+            Some(_) if end > i => (chars[i..end].iter().collect::<String>(), end),
+            _ => return Ok(None),
+        };
+
+        let entry = if designator == "!" {
+            self.entries.last()
+        } else if let Ok(n) = designator.parse::<isize>() {
+            let index = if n < 0 {
+                self.entries.len().checked_sub(n.unsigned_abs())
+            } else {
+                (n as usize).checked_sub(1)
+            };
+            index.and_then(|index| self.entries.get(index))
+        } else {
+            self.entries
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(&designator))
+        };
+        match entry {
+            Some(entry) => Ok(Some((entry, next))),
+            None => Err(format!("!{}", designator)),
+        }
+    }
+}
+
+static mut HISTORY: History = History::new();
+
+/// Get a handle to the shell's history
+pub fn history() -> &'static mut History {
+    unsafe { &mut *std::ptr::addr_of_mut!(HISTORY) }
+}
+
+/// Load the history file: `$HISTFILE`, or `~/.rustymsh_history`. Setting
+/// `HISTFILE` to an empty string keeps the history out of any file.
+pub fn init() {
+    let file = match vars().get("HISTFILE") {
+        Some("") => return,
+        Some(file) => PathBuf::from(file),
+        None => match vars().get("HOME") {
+            Some(home) => PathBuf::from(home).join(".rustymsh_history"),
+            None => return,
+        },
+    };
+    history().load(file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> History {
+        let mut history = History::new();
+        for line in &["/bin/echo one", "jobs", "jobs", "", "/bin/echo two\n"] {
+            history.add(line);
+        }
+        history
+    }
+
+    #[test]
+    fn test_add() {
+        assert_eq!(
+            sample().entries(),
+            &["/bin/echo one", "jobs", "/bin/echo two"]
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let history = sample();
+        let expand = |line| history.expand(line);
+        assert_eq!(expand("!!"), Ok(Some("/bin/echo two".to_string())));
+        assert_eq!(expand("!1 x"), Ok(Some("/bin/echo one x".to_string())));
+        assert_eq!(
+            expand("!-2; !!"),
+            Ok(Some("jobs; /bin/echo two".to_string()))
+        );
+        assert_eq!(expand("!/bin"), Ok(Some("/bin/echo two".to_string())));
+        assert_eq!(expand("!j&"), Ok(Some("jobs&".to_string())));
+        assert_eq!(
+            expand("echo \"it's\" !!"),
+            Ok(Some("echo \"it's\" /bin/echo two".to_string()))
+        );
+        assert_eq!(
+            expand("echo \"!!\" '\"' !!"),
+            Ok(Some(
+                "echo \"/bin/echo two\" '\"' /bin/echo two".to_string()
+            ))
+        );
+        assert_eq!(expand("!4"), Err("!4".to_string()));
+        assert_eq!(expand("!0"), Err("!0".to_string()));
+        assert_eq!(expand("!nope"), Err("!nope".to_string()));
+    }
+
+    #[test]
+    fn test_no_expansion() {
+        let history = sample();
+        for line in &[
+            "echo hi",
+            "echo '!!'",
+            "echo '\"!!'",
+            "echo \\!!",
+            "echo ! a!=b",
+            "echo a!",
+        ] {
+            assert_eq!(history.expand(line), Ok(None), "{}", line);
+        }
+        assert_eq!(History::new().expand("!!"), Err("!!".to_string()));
+    }
+}
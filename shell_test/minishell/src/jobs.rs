@@ -14,15 +14,19 @@
   an atomic variable to signal the presence/absence of a job, so that no job
   can ever be found in an inconsistent state.
 */
+use crate::resources::CpuTimes;
 use crate::util::{MAX_JOBID, MAX_NUM_JOBS};
+use nix::sys::termios::Termios;
 use nix::unistd::Pid;
-type Jid = i32;
+pub type Jid = i32;
 /// The runstate of a job in the system
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum JobState {
-    FG,   // Job is the foreground job
-    BG,   // Job is the background job
-    Stop, // Job is stopped (from receiving SIGSTOP or SIGTSTP)
+    FG,          // Job is the foreground job
+    BG,          // Job is the background job
+    Stop,        // Job is stopped (from receiving SIGSTOP or SIGTSTP)
+    Done(i32),   // Background job exited with this status, but the user hasn't been told yet
+    Killed(i32), // Background job was killed, which has been reported; 128 plus the signal
 }
 
 // The errors that can arise when attempting to manipulate the joblist
@@ -33,31 +37,84 @@ pub enum JobListError {
     NoSuchPid(Pid), // The latter two options are generally unused in favor of Option
     NoSuchJid(Jid),
 }
+
+impl std::fmt::Display for JobListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JobListError::TooManyJobs => write!(f, "too many jobs"),
+            JobListError::InconsistentState => write!(f, "the joblist is inconsistent"),
+            JobListError::NoSuchPid(pid) => write!(f, "({}): no such job", pid),
+            JobListError::NoSuchJid(jid) => write!(f, "%{}: no such job", jid),
+        }
+    }
+}
+
+/// A user-supplied reference to a job, as accepted by fg, bg, kill, wait, and
+/// friends. Either a raw PID (`1234`) or a job ID (`%2`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JobSpec {
+    Pid(Pid),
+    Jid(Jid),
+}
+impl std::fmt::Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JobState::BG => write!(f, "Running"),
+            JobState::FG => write!(f, "Foreground"),
+            JobState::Stop => write!(f, "Stopped"),
+            JobState::Done(0) => write!(f, "Done"),
+            JobState::Done(status) => write!(f, "Exit {}", status),
+            JobState::Killed(_) => write!(f, "Terminated"),
+        }
+    }
+}
+
 /// A representation of a single job being controlled by the shell
 pub struct JobStruct {
     pid: Pid,
     jid: Jid,
     state: JobState,
     cmdline: String,
+    tmodes: Option<Termios>, // Terminal modes to restore when the job is next in the fg
 }
 
 // Simple getter/setter functions to ensure uniformity
 impl JobStruct {
     pub fn jid(&self) -> Jid {
-        return self.jid;
+        self.jid
     }
     pub fn pid(&self) -> Pid {
-        return self.pid;
+        self.pid
     }
     pub fn state(&self) -> JobState {
-        return self.state;
+        self.state
     }
     pub fn cmdline(&self) -> &str {
-        return &self.cmdline;
+        &self.cmdline
+    }
+    pub fn tmodes(&self) -> Option<&Termios> {
+        self.tmodes.as_ref()
     }
     pub fn set_state(&mut self, state: JobState) {
         self.state = state;
     }
+    pub fn set_tmodes(&mut self, tmodes: Option<Termios>) {
+        self.tmodes = tmodes;
+    }
+}
+
+impl std::fmt::Display for JobStruct {
+    /// Formats the job the way the `jobs` builtin lists it
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] ({}) {} {}",
+            self.jid,
+            self.pid,
+            self.state,
+            self.cmdline.trim()
+        )
+    }
 }
 
 /// A controller for all the active jobs in a given system. Only one of these
@@ -65,6 +122,17 @@ impl JobStruct {
 pub struct JobList {
     jobvec: [Option<JobStruct>; MAX_NUM_JOBS],
     nextjid: Jid,
+    fg_status: i32,     // Status of the last foreground job to exit, stop or be killed
+    fg_times: CpuTimes, // CPU time of the foreground jobs reaped, for `time`
+    // Children that aren't jobs, like the commands of substitutions, and their
+    // statuses once they've been reaped
+    helpers: Vec<(Pid, Option<i32>)>,
+}
+
+impl Default for JobList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl JobList {
@@ -73,6 +141,9 @@ impl JobList {
         Self {
             jobvec: Default::default(),
             nextjid: 1,
+            fg_status: 0,
+            fg_times: CpuTimes::default(),
+            helpers: Vec::new(),
         }
     }
     /// Return the largest JobID that is currently allocated.
@@ -94,24 +165,24 @@ impl JobList {
     ) -> Result<Jid, JobListError> {
         let jid = self.nextjid;
         // Ignore validity checks because Pid has checked constructor
-        for job_slot in self.jobvec.iter_mut().filter(|x| x.is_none()) {
-            *job_slot = Some(JobStruct {
-                pid,
-                state,
-                jid,
-                cmdline: cmdline.to_string(),
-            });
-            // After assigning jobid, increment it, wrapping if needed. Wrapping
-            // operates in [1,MAXJOBS] instead of [0, MAXJOBS) so we do custom logic :(
-            self.nextjid += 1;
-            if self.nextjid > MAX_JOBID {
-                self.nextjid = 1;
-            }
-            let j = (&*job_slot).as_ref().unwrap();
-            return Ok(j.jid);
+        let job_slot = match self.jobvec.iter_mut().find(|x| x.is_none()) {
+            Some(slot) => slot,
+            None => return Err(JobListError::TooManyJobs), // No None slots in the jobvec
+        };
+        *job_slot = Some(JobStruct {
+            pid,
+            state,
+            jid,
+            cmdline: cmdline.to_string(),
+            tmodes: None,
+        });
+        // After assigning jobid, increment it, wrapping if needed. Wrapping
+        // operates in [1,MAXJOBS] instead of [0, MAXJOBS) so we do custom logic :(
+        self.nextjid += 1;
+        if self.nextjid > MAX_JOBID {
+            self.nextjid = 1;
         }
-        // No None slots found in the jobvec
-        return Err(JobListError::TooManyJobs);
+        Ok(jid)
     }
 
     /// Deletes a job with the requested PID. Returns an Err if there is no such job.
@@ -123,7 +194,47 @@ impl JobList {
                 return Ok(());
             }
         }
-        return Err(JobListError::NoSuchPid(pid));
+        Err(JobListError::NoSuchPid(pid))
+    }
+
+    /// The status of the last foreground job to finish, shell-style: its exit
+    /// code, or 128 plus the number of the signal that killed or stopped it.
+    pub fn fg_status(&self) -> i32 {
+        self.fg_status
+    }
+    pub fn set_fg_status(&mut self, status: i32) {
+        self.fg_status = status;
+    }
+
+    /// Add up the CPU time of foreground jobs as they finish or stop
+    pub fn add_fg_times(&mut self, times: CpuTimes) {
+        self.fg_times = self.fg_times + times;
+    }
+    /// The CPU time added up since the last call
+    pub fn take_fg_times(&mut self) -> CpuTimes {
+        std::mem::take(&mut self.fg_times)
+    }
+
+    /// Keep track of a child that isn't a job, so that its status is kept when
+    /// it's reaped
+    pub fn add_helper(&mut self, pid: Pid) {
+        self.helpers.push((pid, None));
+    }
+    /// Record the status of a helper that has been reaped. Does nothing if the
+    /// child isn't a helper.
+    pub fn set_helper_status(&mut self, pid: Pid, status: i32) {
+        if let Some(helper) = self.helpers.iter_mut().find(|x| x.0 == pid) {
+            helper.1 = Some(status);
+        }
+    }
+    /// The status of a helper, if it has been reaped
+    pub fn helper_status(&self, pid: Pid) -> Option<i32> {
+        self.helpers.iter().find(|x| x.0 == pid).and_then(|x| x.1)
+    }
+    /// Stop keeping track of a helper, returning its status if it was reaped
+    pub fn remove_helper(&mut self, pid: Pid) -> Option<i32> {
+        let index = self.helpers.iter().position(|x| x.0 == pid)?;
+        self.helpers.swap_remove(index).1
     }
 
     pub fn fgpid(&self) -> Option<Pid> {
@@ -145,7 +256,7 @@ impl JobList {
         None // No job found
     }
 
-    /// Get a job by its PID
+    /// Get a job by its JID
     pub fn getjob_jid(&mut self, jid: Jid) -> Option<&mut JobStruct> {
         for job in self.jobvec.iter_mut().filter(|x| x.is_some()) {
             if job.as_ref().unwrap().jid == jid {
@@ -155,6 +266,64 @@ impl JobList {
         None // No job found
     }
 
+    /// Get a job by a user-supplied job specification
+    pub fn getjob(&mut self, spec: JobSpec) -> Option<&mut JobStruct> {
+        match spec {
+            JobSpec::Pid(pid) => self.getjob_pid(pid),
+            JobSpec::Jid(jid) => self.getjob_jid(jid),
+        }
+    }
+
+    /// The "current" job, i.e. the one that fg/bg/wait/disown would act on
+    /// if no job is named. Like bash, this is the most recently created job.
+    pub fn current_jid(&self) -> Option<Jid> {
+        match self.maxjid() {
+            0 => None,
+            jid => Some(jid),
+        }
+    }
+
+    /// The marker that bash prints after a job ID: `+` for the current job, `-`
+    /// for the previous job, and a space for everything else.
+    pub fn marker(&self, jid: Jid) -> char {
+        let mut jids: Vec<Jid> = self.iter().map(|job| job.jid).collect();
+        jids.sort_unstable_by(|a, b| b.cmp(a));
+        match jids.iter().position(|&x| x == jid) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Remove every job in the Done or Killed state from the list, returning
+    /// them (along with their marker, as computed before removal) in order of
+    /// job ID so that they can be reported to the user.
+    pub fn take_done(&mut self) -> Vec<(char, JobStruct)> {
+        let markers: Vec<(Jid, char)> = self
+            .iter()
+            .map(|job| (job.jid, self.marker(job.jid)))
+            .collect();
+
+        let mut done = Vec::new();
+        for job_slot in self.jobvec.iter_mut() {
+            if let Some(JobState::Done(_)) | Some(JobState::Killed(_)) =
+                job_slot.as_ref().map(|job| job.state)
+            {
+                let job = job_slot.take().unwrap();
+                let marker = markers.iter().find(|(jid, _)| *jid == job.jid).unwrap().1;
+                done.push((marker, job));
+            }
+        }
+        self.nextjid = self.maxjid() + 1;
+        done.sort_by_key(|(_, job)| job.jid);
+        done
+    }
+
+    /// Iterate over all the jobs currently in the list, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = &JobStruct> {
+        self.jobvec.iter().flat_map(|x| x.iter())
+    }
+
     /// Map process ID to job ID
     pub fn pid2jid(&self, pid: Pid) -> Option<Jid> {
         for job in self.jobvec.iter().flat_map(|x| x.iter()) {
@@ -177,23 +346,71 @@ impl JobList {
     // List the jerbs
     pub fn listjobs(&self) -> Result<String, JobListError> {
         let mut output = String::new();
-        for job in self.jobvec.iter().flat_map(|x| x.iter()) {
-            let jid = job.jid;
-            let pid = job.pid;
-            let state = match job.state {
-                JobState::BG => "Running",
-                JobState::FG => "Foreground",
-                JobState::Stop => "Stopped",
-            };
-            let jobstr = format!(
-                "[{}] ({}) {} {}",
-                jid,
-                pid,
-                state,
-                (&job.cmdline[..]).trim()
-            );
-            output = format!("{}{}\n", output, jobstr);
+        for job in self.iter() {
+            output = format!("{}{}\n", output, job);
         }
         Ok(output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker() {
+        let mut jobs = JobList::new();
+        jobs.addjob(Pid::from_raw(100), JobState::BG, "a").unwrap();
+        jobs.addjob(Pid::from_raw(101), JobState::BG, "b").unwrap();
+        jobs.addjob(Pid::from_raw(102), JobState::BG, "c").unwrap();
+        assert_eq!(jobs.marker(3), '+');
+        assert_eq!(jobs.marker(2), '-');
+        assert_eq!(jobs.marker(1), ' ');
+    }
+
+    #[test]
+    fn test_take_done() {
+        let mut jobs = JobList::new();
+        jobs.addjob(Pid::from_raw(100), JobState::BG, "a").unwrap();
+        jobs.addjob(Pid::from_raw(101), JobState::BG, "b").unwrap();
+        jobs.addjob(Pid::from_raw(102), JobState::BG, "c").unwrap();
+        jobs.addjob(Pid::from_raw(103), JobState::BG, "d").unwrap();
+        jobs.getjob_jid(3).unwrap().set_state(JobState::Done(0));
+        jobs.getjob_jid(1).unwrap().set_state(JobState::Done(3));
+        jobs.getjob_jid(4).unwrap().set_state(JobState::Killed(143));
+
+        let done = jobs.take_done();
+        assert_eq!(done.len(), 3);
+        assert_eq!(done[0].0, ' ');
+        assert_eq!(done[0].1.jid(), 1);
+        assert_eq!(format!("{}", done[0].1.state()), "Exit 3");
+        assert_eq!(done[1].0, '-');
+        assert_eq!(done[1].1.jid(), 3);
+        assert_eq!(format!("{}", done[1].1.state()), "Done");
+        assert_eq!(done[2].0, '+');
+        assert_eq!(done[2].1.jid(), 4);
+        assert_eq!(done[2].1.state(), JobState::Killed(143));
+
+        // Only job 2 remains, so the next job should reuse jid 3
+        assert_eq!(jobs.iter().count(), 1);
+        assert_eq!(jobs.addjob(Pid::from_raw(104), JobState::BG, "e"), Ok(3));
+    }
+
+    #[test]
+    fn test_helpers() {
+        let mut jobs = JobList::new();
+        jobs.add_helper(Pid::from_raw(100));
+        jobs.add_helper(Pid::from_raw(101));
+        jobs.set_helper_status(Pid::from_raw(101), 3);
+        jobs.set_helper_status(Pid::from_raw(102), 4);
+        assert_eq!(jobs.helper_status(Pid::from_raw(100)), None);
+        assert_eq!(jobs.helper_status(Pid::from_raw(101)), Some(3));
+        assert_eq!(jobs.helper_status(Pid::from_raw(102)), None);
+
+        // Helpers aren't jobs
+        assert_eq!(jobs.iter().count(), 0);
+        assert_eq!(jobs.remove_helper(Pid::from_raw(101)), Some(3));
+        assert_eq!(jobs.remove_helper(Pid::from_raw(101)), None);
+        assert_eq!(jobs.remove_helper(Pid::from_raw(100)), None);
+    }
+}
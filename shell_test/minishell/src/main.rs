@@ -7,42 +7,80 @@ msh.c, and the signal handlers.
 See how many places it's possible to remove uses of to_string() or cloning. May
 involve parameterizing over lifetimes.
 
-Rework the handler printing code to be less convoluted.
-
-Parameterize a set of enums for error reporting so that it can be centralized?
-
 Clean up the joblist API to make it easier to use.
 */
+pub mod aliases;
+pub mod ast;
+pub mod builtins;
+pub mod dirs;
+pub mod editor;
+pub mod errors;
+pub mod functions;
+pub mod glob;
+pub mod history;
 pub mod jobs;
+pub mod prompt;
+pub mod resources;
+pub mod substitution;
+pub mod terminal;
+pub mod traps;
 pub mod util;
-
+pub mod vars;
+
+use crate::aliases::aliases;
+use crate::ast::{AndOr, Command, CommandKind, Connector, List, ListItem};
+use crate::builtins::{builtin_cmd, is_builtin};
+use crate::errors::ShellError;
+use crate::functions::{functions, Function};
+use crate::history::history;
 use crate::jobs::{JobList, JobState};
+use crate::resources::{CpuTimes, Timer};
 use crate::util::{
-    cast_execve_args, format_string_int, parse_numerical, parse_shell_args, signal_write_err,
-    signal_write_out, unix_error, ParseFailReason,
+    cast_execve_args, expand_command, parse_shell_args, signal_write_err, signal_write_out,
+    unix_error, Assignment, ExpandEnv, Input, MsgBuf, ParseFailReason,
 };
+use crate::vars::vars;
 
 use libc::{c_int, sigset_t};
+use nix::errno::Errno;
 use nix::sys::signal::{kill, sigprocmask, SigHandler, SigSet, SigmaskHow, Signal};
-use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::{fork, setpgid, ForkResult, Pid};
-use std::ffi::CStr;
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
+use nix::unistd::{close, dup2, fork, getpid, setpgid, ForkResult, Pid};
+use std::ffi::{CStr, CString};
+use std::fmt::Write as _;
 use std::io::{self, Write};
 use std::mem::MaybeUninit;
-
-const PROMPT_STR: &str = "msh> ";
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /* Since we'll need to access this variable from signal handlers, it needs to be
  * global, and unprotected by synchronization mechanisms. This makes it unsafe
- * to access. Fortunately, this also clearly illuminates all potential race
- * conditions in the shell (anytime we touch JOBS_LIST in an unsafe block without
- * blocking signals first).
+ * to access. All accesses go through joblist(), so the potential race
+ * conditions in the shell are exactly the calls to joblist() made without
+ * blocking signals first.
  *
  * Also note that we will initialize JOBS_LIST to Some() in the start of main(),
  * so all other accesses will unwrap() instead of smart handling.
  */
 static mut JOBS_LIST: Option<JobList> = None;
 
+/// Get a handle to the global joblist. Anything that needs a consistent view
+/// of the jobs must block signals with block_job_signals() first.
+fn joblist() -> &'static mut JobList {
+    unsafe { (*std::ptr::addr_of_mut!(JOBS_LIST)).as_mut().unwrap() }
+}
+
+/// Whether jobs get process groups of their own. Subshells turn this off, and
+/// the sigchld handler reads it, hence the atomic.
+static JOB_CONTROL: AtomicBool = AtomicBool::new(true);
+
+fn job_control() -> bool {
+    JOB_CONTROL.load(Ordering::SeqCst)
+}
+
+/// Whether to echo command lines before running them (-v)
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
 /* Overall architecture is a REPL: we read a command from stdin, execute it, and
 then wait for the next command. Whether we let the user enter a new command
 immediately or not is contingent on whether a fg/bg job was requested: if bg,
@@ -68,10 +106,9 @@ fn main() {
     nix::unistd::dup2(1, 2).expect("Could not redirect stderr to stdout");
 
     // As amazing as clap and friends are, I don't want to pull in a dependency
-    // to parse three flags for a toy project. Just do it manually...
-    let emit_prompt;
-    let (_, emit) = parse_shell_args();
-    emit_prompt = emit;
+    // to parse a few flags for a toy project. Just do it manually...
+    let args = parse_shell_args();
+    VERBOSE.store(args.verbose, Ordering::SeqCst);
 
     util::install_sighandler(SigHandler::Handler(sigint_handler), Signal::SIGINT);
     util::install_sighandler(SigHandler::Handler(sigchld_handler), Signal::SIGCHLD);
@@ -82,6 +119,44 @@ fn main() {
     unsafe {
         JOBS_LIST = Some(JobList::new());
     }
+    vars::init();
+    vars().set_arg0(args.arg0);
+    vars().set_params(args.params);
+    dirs::init();
+
+    // Scripts and -c commands are run without job control, like bash does
+    let script = match args.input {
+        Input::Stdin => None,
+        Input::Command(command) => Some(command),
+        Input::Script(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                let errno = nix::errno::from_i32(e.raw_os_error().unwrap_or(0));
+                eprintln!("{}: {}", path, errno.desc());
+                std::process::exit(127);
+            }
+        },
+    };
+    if let Some(script) = script {
+        disable_job_control();
+        let mut status = 0;
+        for command in ast::split_script(&script) {
+            report_done_jobs(false);
+            status = eval(&command).unwrap_or(status);
+        }
+        vars().set_last_status(status);
+        exit_shell();
+    }
+
+    // If we're running on a terminal, take control of it so that we can hand
+    // it to foreground jobs later on. Lines typed at a terminal go through the
+    // line editor, and are kept in the history.
+    terminal::init();
+    let interactive = nix::unistd::isatty(0).unwrap_or(false);
+    if interactive {
+        history::init();
+    }
+    let emit_prompt = args.emit_prompt;
 
     /* The following block is the main REPL of rustymsh. The REPL reads a line
      * from stdin, executes it, and fflushes the result so that we don't have
@@ -89,207 +164,507 @@ fn main() {
     let mut inp_buf = String::new();
     let stdin = io::stdin();
     loop {
-        if emit_prompt {
-            print!("{}", PROMPT_STR);
-            std::io::stdout().flush().expect("Failed to flush stdout.");
+        traps::run_pending();
+        report_done_jobs(emit_prompt);
+        let prompt = if emit_prompt {
+            prompt::prompt()
+        } else {
+            String::new()
+        };
+
+        if interactive {
+            let line = match editor::read_line(&prompt) {
+                Some(line) => line,
+                None => exit_shell(), // ^D
+            };
+            if let Some(line) = expand_history(line) {
+                history().add(&line);
+                eval(&line);
+            }
+        } else {
+            print!("{}", prompt);
+            flush_stdout();
+
+            match stdin.read_line(&mut inp_buf) {
+                Ok(0) => exit_shell(), // Reached EOF
+                Ok(_) => {}
+                Err(e) => {
+                    let errno = Errno::from_i32(e.raw_os_error().unwrap_or(0));
+                    ShellError::Sys("read", errno).report();
+                    exit_shell();
+                }
+            }
+            eval(&inp_buf[..]);
+            inp_buf.clear();
         }
+        flush_stdout();
+    }
+}
+
+/// Flush anything buffered for stdout. If that fails, e.g. because the terminal
+/// has gone away, there's no one to complain to.
+fn flush_stdout() {
+    let _ = std::io::stdout().flush();
+}
 
-        let bytes = stdin
-            .read_line(&mut inp_buf)
-            .expect("Could not read from stdin");
-        if bytes == 0 {
-            std::process::exit(0); // Reached EOF--exit successfully
+/// Leave the shell at the end of its input, with the status of the last command
+/// it ran, like `exit` does. The EXIT trap runs first.
+fn exit_shell() -> ! {
+    traps::run_exit_trap();
+    flush_stdout();
+    std::process::exit(vars().last_status())
+}
+
+/// Expand the `!` history references in a line typed at the prompt. Like bash,
+/// show the line if that changed it, and drop it if a reference can't be found.
+fn expand_history(line: String) -> Option<String> {
+    match history().expand(&line) {
+        Ok(None) => Some(line),
+        Ok(Some(expanded)) => {
+            println!("{}", expanded);
+            Some(expanded)
+        }
+        Err(event) => {
+            println!("{}: event not found", event);
+            None
         }
+    }
+}
 
-        eval(&inp_buf[..]);
-        inp_buf.clear();
-        std::io::stdout().flush().expect("Failed to flush stdout.");
+/// Evaluate a command line, from the REPL, a script or a sourced file. Returns
+/// the status of the line, or None if there was nothing to run. A line that
+/// doesn't parse is reported, and fails without running anything.
+fn eval(cmdline: &str) -> Option<i32> {
+    if VERBOSE.load(Ordering::SeqCst) {
+        eprintln!("{}", cmdline.trim_end_matches('\n'));
     }
+
+    let status = match ast::parse(cmdline) {
+        Ok(list) => run_list(&list),
+        Err(ParseFailReason::EmptyLine) => return None, // Empty line is not an error
+        Err(e) => fail(e.into()),
+    };
+    Some(status)
+}
+
+/// Report an error that stopped a command from running, and return the status
+/// that the command gets for it (also recorded as `$?`)
+fn fail(error: ShellError) -> i32 {
+    error.report();
+    vars().set_last_status(error.status());
+    error.status()
 }
 
-/// Evaluate a given command string read from the REPL.
-fn eval(cmdline: &str) -> () {
-    let parse_result = match crate::util::parseline(cmdline) {
-        Ok(args) => args,
-        Err(ParseFailReason::EmptyLine) => return, // Empty line is not an error
-        Err(e) => {
-            eprintln!("Error in parse: {:?}", e);
-            std::process::exit(1);
+/// Expand the words of a simple command just before it runs
+fn expand(words: &[String]) -> errors::Result<(Vec<String>, Vec<Assignment>)> {
+    // Only the command substitutions of these words count for its status
+    substitution::take_status();
+    Ok(expand_command(words, vars())?)
+}
+
+/// Run the items of a list one after the other, returning the status of the
+/// last one. The process substitutions of an item are closed once it's done.
+fn run_list(list: &List) -> i32 {
+    let mut status = 0;
+    for item in &list.items {
+        let mark = substitution::mark();
+        status = if item.background {
+            let status = run_background(item);
+            vars().set_last_status(status);
+            status
+        } else {
+            run_and_or(&item.and_or)
+        };
+        substitution::close_since(mark);
+        traps::run_pending();
+    }
+    status
+}
+
+/// Run the commands of an AndOr in the foreground, skipping the ones that the
+/// `&&`s and `||`s rule out, and return the status of the last one run.
+fn run_and_or(and_or: &AndOr) -> i32 {
+    let mut status = run_command(&and_or.first);
+    for (connector, command) in &and_or.rest {
+        let wanted = match connector {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if wanted {
+            status = run_command(command);
+        }
+    }
+    status
+}
+
+/// Run a single command in the foreground, and make its status available as `$?`
+/// to whatever runs next. A command prefixed with `time` reports how long it
+/// took once it's done.
+fn run_command(command: &Command) -> i32 {
+    let timer = if command.timed {
+        Some(Timer::start())
+    } else {
+        None
+    };
+    let status = match &command.kind {
+        CommandKind::Simple(words) => match aliases().expand(words) {
+            Some((name, line)) => run_alias(name, &line),
+            None => match expand(words) {
+                Ok((argv, assignments)) => run_simple(argv, &assignments, &command.text),
+                Err(e) => fail(e),
+            },
+        },
+        CommandKind::Subshell(list) => spawn_job(&command.text, JobState::FG, || {
+            enter_subshell();
+            run_list(list)
+        }),
+        CommandKind::Group(list) => run_list(list),
+        CommandKind::FunctionDef(name, body) => {
+            let function = Function {
+                body: (**body).clone(),
+                text: command.text.clone(),
+            };
+            functions().define(name, function);
+            0
         }
     };
-    let jobstate = parse_result.0;
-    let argv = parse_result.1;
-    if builtin_cmd(&argv) {
-        return (); // builtin_cmd executes the command, so we should just return
+    if let Some(timer) = timer {
+        timer.report();
     }
+    vars().set_last_status(status);
+    status
+}
+
+/// Run the command line that the alias `name` expanded to
+fn run_alias(name: String, line: &str) -> i32 {
+    aliases().start_expanding(name);
+    let status = match ast::parse(line) {
+        Ok(list) => run_list(&list),
+        Err(ParseFailReason::EmptyLine) => 0,
+        Err(e) => fail(e.into()),
+    };
+    aliases().finish_expanding();
+    status
+}
+
+/// Run an expanded simple command in the foreground: functions and builtins
+/// run in the shell itself, and anything else becomes a foreground job.
+fn run_simple(argv: Vec<String>, assignments: &[(String, String)], cmdline: &str) -> i32 {
+    // A command of nothing but assignments sets shell variables. Its status is
+    // that of the last command substitution in it, if there was one.
+    if argv.is_empty() {
+        for (name, value) in assignments {
+            vars().set(name, value.clone());
+        }
+        return substitution::take_status().unwrap_or(0);
+    }
+
+    if let Some(function) = functions().get(&argv[0]) {
+        return run_function(function.clone(), argv, assignments);
+    }
+
+    // Builtins run with job signals blocked, since most of them read or modify
+    // the joblist. They see the command's assignments too, but only while they
+    // run.
+    let old_blockset = block_job_signals();
+    let saved_vars = vars().assign_temporarily(assignments);
+    let status = builtin_cmd(&argv);
+    vars().restore(saved_vars);
+    restore_signals(&old_blockset);
+
+    match status {
+        Some(status) => status,
+        None => spawn_job(cmdline, JobState::FG, || exec(argv, assignments)),
+    }
+}
+
+/// Call a function with the arguments in argv[1..] as its positional parameters.
+/// Like a builtin, it sees the command's assignments only while it runs.
+fn run_function(function: Function, argv: Vec<String>, assignments: &[(String, String)]) -> i32 {
+    if !functions().enter() {
+        return fail(ShellError::TooDeep(argv[0].clone()));
+    }
+    let saved_vars = vars().assign_temporarily(assignments);
+    let saved_params = vars().set_params(argv[1..].to_vec());
+    let status = run_command(&function.body);
+    vars().set_params(saved_params);
+    vars().restore(saved_vars);
+    functions().leave();
+    status
+}
+
+/// Run a list item as a single background job. A lone program is run directly,
+/// and anything else gets a subshell to run it, like bash.
+fn run_background(item: &ListItem) -> i32 {
+    let and_or = &item.and_or;
+    let lone = and_or.rest.is_empty() && !and_or.first.timed;
+    if let (CommandKind::Simple(words), true) = (&and_or.first.kind, lone) {
+        if aliases().expand(words).is_some() {
+            return spawn_job(&item.text, JobState::BG, || {
+                enter_subshell();
+                run_and_or(and_or)
+            });
+        }
+        let (argv, assignments) = match expand(words) {
+            Ok(expanded) => expanded,
+            Err(e) => return fail(e),
+        };
+        if argv
+            .first()
+            .is_some_and(|name| !is_builtin(name) && functions().get(name).is_none())
+        {
+            return spawn_job(&item.text, JobState::BG, || exec(argv, &assignments));
+        }
+        return spawn_job(&item.text, JobState::BG, || {
+            enter_subshell();
+            run_simple(argv, &assignments, &and_or.first.text)
+        });
+    }
+
+    spawn_job(&item.text, JobState::BG, || {
+        enter_subshell();
+        run_and_or(and_or)
+    })
+}
 
+/// Fork a new job, add it to the joblist, and run `child` in it. The child exits
+/// with the status that `child` returns. A foreground job is waited for, and its
+/// status returned, while a background job is announced.
+fn spawn_job<F: FnOnce() -> i32>(cmdline: &str, jobstate: JobState, child: F) -> i32 {
     /* We need to disable interrupts until we've added the child into the
     jobstructs--otherwise, we can be interrupted when the jobstruct does not
     reflect the state of the world (bad!). This is really more important for
-    the parent--the child can immediately unblock the signals */
-    let mut needs_block = SigSet::empty();
-    let mut old_blockset = SigSet::empty();
-    needs_block.add(Signal::SIGINT);
-    needs_block.add(Signal::SIGTSTP);
-    needs_block.add(Signal::SIGCHLD);
-
-    sigprocmask(
-        SigmaskHow::SIG_BLOCK,
-        Some(&needs_block),
-        Some(&mut old_blockset),
-    )
-    .expect("Could not block signals in sigprocmask.");
+    the parent--the child can immediately unblock the signals. */
+    let old_blockset = block_job_signals();
 
     match fork() {
         Ok(ForkResult::Parent { child: pid, .. }) => {
-            let jid;
-
-            unsafe {
-                jid = JOBS_LIST
-                    .as_mut()
-                    .unwrap()
-                    .addjob(pid, jobstate, cmdline)
-                    .unwrap();
-            }
+            let jid = match joblist().addjob(pid, jobstate, cmdline) {
+                Ok(jid) => jid,
+                Err(e) => {
+                    // The sigchld handler reaps children that aren't in the
+                    // joblist, so the child can just be dropped
+                    let _ = kill(pid, Signal::SIGKILL);
+                    terminal::reclaim();
+                    restore_signals(&old_blockset);
+                    return fail(e.into());
+                }
+            };
 
-            if jobstate == JobState::FG {
-                waitfg(pid);
+            let status = if jobstate == JobState::FG {
+                waitfg(pid)
             } else {
                 println!("[{}] ({}) {}", jid, pid, cmdline.trim());
-            }
+                0
+            };
 
-            sigprocmask(SigmaskHow::SIG_SETMASK, Some(&old_blockset), None)
-                .expect("Could not unblock signals from parent process");
+            restore_signals(&old_blockset);
+            status
         }
         Ok(ForkResult::Child) => {
             // Detach the child into its own process group so that it doesn't get
-            // signals that are only meant for the shell/foreground process
-            setpgid(Pid::from_raw(0), Pid::from_raw(0)).expect("Could not setpgid");
-            sigprocmask(SigmaskHow::SIG_SETMASK, Some(&old_blockset), None)
-                .expect("Could not unblock signals from child process");
-
-            // Type-level faffery to convert things into &[CStr], which is needed for execve
-            let (argv, env) = cast_execve_args(argv);
-            let argv: Vec<&CStr> = argv.iter().map(|x| &x[..]).collect();
-            let env: Vec<&CStr> = env.iter().map(|x| &x[..]).collect();
-
-            if let Err(_) = nix::unistd::execve(&argv[0], &argv, &env) {
-                println!("{}: Command not found", argv[0].to_str().unwrap());
-                std::process::exit(-1);
+            // signals that are only meant for the shell/foreground process. In a
+            // subshell, everything stays in the subshell's group instead.
+            if job_control() {
+                if setpgid(Pid::from_raw(0), Pid::from_raw(0)).is_err() {
+                    ShellError::Sys("setpgid", Errno::last()).report();
+                }
+                // Foreground jobs get the terminal. The parent does this too,
+                // since we can't know which of us will get to run first.
+                if jobstate == JobState::FG {
+                    terminal::give_to(getpid(), None);
+                }
             }
+            run_child(&old_blockset, child)
+        }
+        Err(_) => {
+            restore_signals(&old_blockset);
+            fail(ShellError::Sys("fork", Errno::last()))
         }
-        Err(_) => unix_error("Call to fork() failed."),
     }
 }
 
-/// Checks to see if argv corresponds to built-in, and executes it if so.
-/// Returns true if the command was a built-in.
-fn builtin_cmd(argv: &Vec<String>) -> bool {
-    match &(argv[0])[..] {
-        "jobs" => {
-            unsafe {
-                let job_str = JOBS_LIST.as_ref().unwrap().listjobs();
-                print!("{}", job_str.unwrap());
-            }
-            true
+/// Fork a helper for a command or process substitution: a subshell that runs
+/// `command` with its stdout on `write_end`, the other end of the pipe being
+/// `read_end`. Helpers stay in the shell's process group, and aren't jobs, but
+/// the joblist keeps their statuses for wait_helper().
+fn spawn_helper(command: &str, read_end: RawFd, write_end: RawFd) -> errors::Result<Pid> {
+    // Anything still buffered would be printed by the helper too
+    flush_stdout();
+    let old_blockset = block_job_signals();
+
+    match fork() {
+        Ok(ForkResult::Parent { child: pid, .. }) => {
+            joblist().add_helper(pid);
+            restore_signals(&old_blockset);
+            Ok(pid)
         }
-        "quit" => {
-            std::process::exit(0);
+        Ok(ForkResult::Child) => {
+            let _ = close(read_end);
+            if dup2(write_end, 1).is_err() {
+                ShellError::Sys("dup2", Errno::last()).report();
+                std::process::exit(1);
+            }
+            let _ = close(write_end);
+            run_child(&old_blockset, || {
+                enter_subshell();
+                eval(command).unwrap_or(0)
+            })
         }
-        "fg" | "bg" => {
-            do_bgfg(argv);
-            true
+        Err(_) => {
+            restore_signals(&old_blockset);
+            Err(ShellError::Sys("fork", Errno::last()))
         }
-        _ => false,
     }
 }
 
-/// Handles moving jobs into the foreground and running jobs in the background.
-fn do_bgfg(argv: &Vec<String>) -> () {
-    /* The logic of this function is a little nasty: because we need to do fg/bg
-     * and lookups on pid/jid, there's a lot of potentially redundant code. My
-     * solution is to use these flags to gather data in one phase, then execute
-     * all the actions at once */
-    let usepid;
-    let tofg;
-    let id;
+/// Wait for a helper started by spawn_helper() to finish, returning its status
+fn wait_helper(pid: Pid) -> i32 {
+    let old_blockset = block_job_signals();
+    wait_until(|jobs| jobs.helper_status(pid).is_some());
+    let status = joblist().remove_helper(pid).unwrap_or(0);
+    restore_signals(&old_blockset);
+    status
+}
 
-    if argv.len() == 1 {
-        println!("{} command requires PID or %jobid argument", argv[0]);
-        return ();
+/// Finish setting up a freshly forked job or helper, and run `child` in it. The
+/// child exits with the status that `child` returns.
+fn run_child<F: FnOnce() -> i32>(old_blockset: &SigSet, child: F) -> ! {
+    terminal::reset_child_signals();
+    traps::reset_for_child();
+    if let Err(e) = resources::apply_limits() {
+        e.report();
+        std::process::exit(e.status());
     }
+    // Jobs started by a builtin like source inherit its blocked mask,
+    // so unblock the job signals explicitly.
+    restore_signals(old_blockset);
+    let _ = sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&job_signals()), None);
+
+    let status = child();
+    vars().set_last_status(status);
+    exit_shell();
+}
 
-    if argv[0] == "fg" {
-        tofg = true;
-    } else if argv[0] == "bg" {
-        tofg = false;
-    } else {
-        // This function should only be entered if argv[0] is fg or bg, but I don't
-        // want to rely on someone never changing the caller code...
-        unreachable!();
-    }
+/// Replace this process with the program in argv. Only returns, with the status
+/// bash uses for a missing command, if the program couldn't be run.
+fn exec(argv: Vec<String>, assignments: &[(String, String)]) -> i32 {
+    // Type-level faffery to convert things into &[CStr], which is needed for execve
+    let (argv, env) = cast_execve_args(argv, vars().environ(assignments));
+    let argv: Vec<&CStr> = argv.iter().map(|x| &x[..]).collect();
+    let env: Vec<&CStr> = env.iter().map(|x| &x[..]).collect();
+
+    // Names without a slash are looked up in $PATH first, as in any shell
+    let program = match dirs::find_in_path(&argv[0].to_string_lossy()) {
+        Some(path) => CString::new(path).unwrap(),
+        None => argv[0].to_owned(),
+    };
+    let _ = nix::unistd::execve(&program, &argv, &env);
+    let error = ShellError::CommandNotFound(argv[0].to_string_lossy().into_owned());
+    error.report();
+    error.status()
+}
 
-    let arg = &argv[1];
-    let arg = arg.as_bytes();
-    if arg[0] == b'%' {
-        usepid = false;
-        id = match parse_numerical(&arg[1..]) {
-            Ok(x) => x,
-            Err(_) => {
-                println!("{}: argument must be a PID or %jobid", argv[0]);
-                return ();
-            }
-        }
-    } else {
-        usepid = true;
-        id = match parse_numerical(&arg[..]) {
-            Ok(x) => x,
-            Err(_) => {
-                println!("{}: argument must be a PID or %jobid", argv[0]);
-                return ();
-            }
-        }
+/// Turn a freshly forked child into a subshell: a copy of the shell that runs
+/// a list without job control. Everything it runs stays in its process group,
+/// so that the whole job is stopped, continued and killed together, and the
+/// default actions of SIGINT and SIGTSTP take care of the subshell itself.
+fn enter_subshell() {
+    let old_blockset = block_job_signals();
+    disable_job_control();
+    // The parent's jobs aren't ours to manage
+    unsafe {
+        JOBS_LIST = Some(JobList::new());
     }
+    terminal::release();
+    restore_signals(&old_blockset);
+}
 
-    let maybejob = unsafe {
-        if usepid {
-            JOBS_LIST.as_mut().unwrap().getjob_pid(Pid::from_raw(id))
-        } else {
-            JOBS_LIST.as_mut().unwrap().getjob_jid(id)
-        }
-    };
+/// Stop giving jobs process groups of their own. Everything the shell runs then
+/// stays in the shell's process group, so SIGINT and SIGTSTP get their default
+/// actions, and take effect on the shell along with whatever it's running.
+fn disable_job_control() {
+    JOB_CONTROL.store(false, Ordering::SeqCst);
+    util::install_sighandler(SigHandler::SigDfl, Signal::SIGINT);
+    util::install_sighandler(SigHandler::SigDfl, Signal::SIGTSTP);
+    util::install_sighandler(SigHandler::SigDfl, Signal::SIGQUIT);
+}
 
-    let job = match maybejob {
-        Some(j) => j,
-        None => {
-            if usepid {
-                println!("({}): No such process", id);
-            } else {
-                println!("%{}: No such job", id);
-            }
-            return ();
+/// Tell the user about background jobs that have finished since the last
+/// prompt, bash-style, and remove them from the joblist. The sigchld handler
+/// only marks these jobs as Done, since printing from signal context isn't
+/// safe. Killed jobs are just removed, since the handler has already said which
+/// signal killed them. Like bash, nothing is printed if the shell isn't
+/// interactive.
+fn report_done_jobs(interactive: bool) {
+    let old_blockset = block_job_signals();
+    let done = joblist().take_done();
+    restore_signals(&old_blockset);
+
+    if !interactive {
+        return;
+    }
+    // Killed jobs were reported by the SIGCHLD handler already
+    for (marker, job) in done {
+        if let JobState::Killed(_) = job.state() {
+            continue;
         }
-    };
+        println!(
+            "[{}]{} {}  {}",
+            job.jid(),
+            marker,
+            job.state(),
+            job.cmdline().trim()
+        );
+    }
+}
 
-    let jid = job.jid();
-    let pid = job.pid();
-    let cmdline = job.cmdline().to_string();
+/// Block the signals whose handlers modify the joblist (INT, TSTP and CHLD),
+/// returning the previous mask so that it can be passed to restore_signals().
+fn block_job_signals() -> SigSet {
+    let mut old_blockset = SigSet::empty();
+    sigprocmask(
+        SigmaskHow::SIG_BLOCK,
+        Some(&job_signals()),
+        Some(&mut old_blockset),
+    )
+    .expect("Could not block signals in sigprocmask.");
+    old_blockset
+}
 
-    let group_id = Pid::from_raw(-pid.as_raw());
-    kill(group_id, Signal::SIGCONT).expect("Could not send SIGCONT");
+/// The signals that block_job_signals() blocks
+fn job_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTSTP);
+    signals.add(Signal::SIGCHLD);
+    signals
+}
 
-    if tofg {
-        job.set_state(JobState::FG);
-        waitfg(pid);
-    } else {
-        job.set_state(JobState::BG);
-        print!("[{}] ({}) {}", jid, pid, cmdline);
+/// Restore a signal mask previously returned by block_job_signals()
+fn restore_signals(old_blockset: &SigSet) {
+    sigprocmask(SigmaskHow::SIG_SETMASK, Some(old_blockset), None)
+        .expect("Could not restore the signal mask");
+}
+
+/// Wait for a foreground process given by pid, returning its status. Assumes
+/// signals that could affect child process state have been blocked by the caller.
+fn waitfg(pid: Pid) -> i32 {
+    terminal::give_to(pid, None);
+    wait_until(|jobs| jobs.fgpid() != Some(pid));
+
+    // Once the job is done with the terminal (for now), take it back. If the job
+    // only stopped, remember how it left the terminal for when it's resumed.
+    let tmodes = terminal::reclaim();
+    if let Some(job) = joblist().getjob_pid(pid) {
+        job.set_tmodes(tmodes);
     }
+    joblist().fg_status()
 }
 
-/// Wait for a foreground process given by pid. Assumes signals that could affect
-/// child process state
-fn waitfg(pid: Pid) -> () {
+/// Suspend the shell until `done` holds for the joblist. Used by waitfg() and
+/// by the wait builtin.
+fn wait_until<F: Fn(&JobList) -> bool>(done: F) {
     // Signals are blocked in this function by the calling scope eval(), which
     // blocks INT, TSTP, and CHLD. Signals will be temporarily unblocked by the
     // sigsuspend call while in this scope.
@@ -307,116 +682,171 @@ fn waitfg(pid: Pid) -> () {
         emptyset.assume_init()
     };
 
-    // Check the jobs list for our foreground job. This is safe ONLY because eval()
+    // Check the jobs list for our condition. This is safe ONLY because eval()
     // has blocked signals for us--otherwise we'd be at risk of race conditions
-    let mut fgpid = unsafe { JOBS_LIST.as_mut().unwrap().fgpid() };
-    while fgpid.is_some() && fgpid.unwrap() == pid {
+    while !done(joblist()) {
         unsafe {
             /* sigsuspend() blocks the program until a signal is recieved. Hopefully,
-            at some point, we'll get a SIGCHLD, the sigchld_handler will remove
-            foreground job from the joblist, and we can exit this loop! */
+            at some point, we'll get a SIGCHLD, the sigchld_handler will update
+            the joblist (e.g. by removing the foreground job), and we can exit
+            this loop! */
             libc::sigsuspend(&emptysigset as *const sigset_t);
-            fgpid = JOBS_LIST.as_mut().unwrap().fgpid();
         }
     }
-
-    ()
 }
 
-extern "C" fn sigquit_handler(_: c_int) -> () {
+extern "C" fn sigquit_handler(sig: c_int) {
+    // A trap on SIGQUIT replaces the usual exit
+    if traps::trapped(sig) {
+        traps::note(sig);
+        return;
+    }
     const QUIT_MSG: &[u8] = b"Terminating after receipt of SIGQUIT signal\n";
     signal_write_err(QUIT_MSG);
     std::process::exit(1);
 }
 
-extern "C" fn sigint_handler(_: c_int) -> () {
-    let fgpid = unsafe { JOBS_LIST.as_ref().unwrap().fgpid() };
+extern "C" fn sigint_handler(sig: c_int) {
+    let fgpid = joblist().fgpid();
 
     if let Some(pid) = fgpid {
         let group_id = Pid::from_raw(-pid.as_raw());
-        kill(group_id, Signal::SIGINT).expect("Could not send SIGINT");
+        // The job may have exited already, which is fine
+        let _ = kill(group_id, Signal::SIGINT);
     }
+    traps::note(sig);
 }
 
-extern "C" fn sigtstp_handler(_: c_int) -> () {
-    let fgpid = unsafe { JOBS_LIST.as_ref().unwrap().fgpid() };
+extern "C" fn sigtstp_handler(sig: c_int) {
+    let fgpid = joblist().fgpid();
 
     if let Some(pid) = fgpid {
         let group_id = Pid::from_raw(-pid.as_raw());
-        kill(group_id, Signal::SIGTSTP).expect("Could not send SIGTSTP");
+        let _ = kill(group_id, Signal::SIGTSTP);
+    }
+    traps::note(sig);
+}
+
+/// waitpid(-1, flags), but with the CPU time of the child as well, which nix
+/// has no wrapper for. Returns None once there are no children left to wait for.
+fn wait_any(flags: WaitPidFlag) -> Option<(WaitStatus, CpuTimes)> {
+    let mut status: c_int = 0;
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+    let pid = unsafe { libc::wait4(-1, &mut status, flags.bits(), usage.as_mut_ptr()) };
+    match pid {
+        -1 => None,
+        0 => Some((WaitStatus::StillAlive, CpuTimes::default())),
+        _ => {
+            let status = WaitStatus::from_raw(Pid::from_raw(pid), status).ok()?;
+            let usage = unsafe { usage.assume_init() };
+            Some((status, CpuTimes::from_rusage(&usage)))
+        }
     }
 }
 
-extern "C" fn sigchld_handler(_: c_int) -> () {
+extern "C" fn sigchld_handler(sig: c_int) {
     // Somebody's dead! Let's gather all their bodies! Note that sigchld may fire
     // multiple times, but we will only recieve the last one, so it's not
     // sufficient to just reap one child here--we have to keep collecting them
     // until no unreaped children are left.
     let mut flags = WaitPidFlag::empty();
     flags.insert(WaitPidFlag::WNOHANG);
-    flags.insert(WaitPidFlag::WUNTRACED);
-    flags.insert(WaitPidFlag::WCONTINUED);
+    // Without job control, a stopped child is left for whoever continues it.
+    // The job stays in the foreground, waiting for the child to finish.
+    if job_control() {
+        flags.insert(WaitPidFlag::WUNTRACED);
+        flags.insert(WaitPidFlag::WCONTINUED);
+    }
 
-    // pid = None converts into waitpid(-1), i.e. wait for all children.
-    // Figure that one out without reading the nix source code, I dare you.
-    while let Ok(status) = waitpid(None, Some(flags)) {
+    while let Some((status, times)) = wait_any(flags) {
         match status {
-            WaitStatus::Exited(pid, _) => unsafe {
-                JOBS_LIST
-                    .as_mut()
-                    .unwrap()
-                    .deletejob(pid)
-                    .expect("Nonexistent job exited! Bug or race condition?")
+            // Children that are not in the joblist are helpers, whose statuses
+            // are kept for whoever waits on them, or were disowned by the user:
+            // reap them, but otherwise leave them be. Background jobs are only
+            // marked as Done here: the main loop will report them and clean
+            // them up before the next prompt.
+            WaitStatus::Exited(pid, status) => match joblist().getjob_pid(pid) {
+                Some(job) if job.state() != JobState::FG => job.set_state(JobState::Done(status)),
+                Some(_) => {
+                    joblist().set_fg_status(status);
+                    joblist().add_fg_times(times);
+                    let _ = joblist().deletejob(pid);
+                }
+                None => joblist().set_helper_status(pid, status),
             },
             WaitStatus::Signaled(pid, signal, _) => {
-                let job = unsafe {
-                    JOBS_LIST
-                        .as_mut()
-                        .unwrap()
-                        .getjob_pid(pid)
-                        .expect("Nonexistent job killed by signal! Bug or race condition?")
+                let job = match joblist().getjob_pid(pid) {
+                    Some(job) => job,
+                    None => {
+                        joblist().set_helper_status(pid, 128 + signal as i32);
+                        continue;
+                    }
                 };
                 let jid = job.jid();
                 let pid = job.pid();
-                unsafe {
-                    JOBS_LIST.as_mut().unwrap().deletejob(pid).unwrap();
+                if job.state() == JobState::FG {
+                    joblist().set_fg_status(128 + signal as i32);
+                    joblist().add_fg_times(times);
+                    // At a terminal, ^C only reaches the foreground job. Like
+                    // bash, act as if the shell had been interrupted too.
+                    if signal == Signal::SIGINT {
+                        traps::note(signal as c_int);
+                    }
+                    let _ = joblist().deletejob(pid);
+                } else {
+                    // Kept, like a Done job, for `wait` to find its status
+                    job.set_state(JobState::Killed(128 + signal as i32));
                 }
 
-                let msgbuf = b"Job [%d] (%d) terminated by signal %d\n";
-                let msg = format_string_int(msgbuf, jid);
-                let msg = format_string_int(&msg[..], pid.as_raw());
-                let msg = format_string_int(&msg[..], signal as i32);
-                signal_write_out(&msg[..]);
+                let mut msg = MsgBuf::new();
+                let _ = writeln!(
+                    msg,
+                    "Job [{}] ({}) terminated by signal {}",
+                    jid, pid, signal as i32
+                );
+                signal_write_out(msg.as_bytes());
             }
             WaitStatus::Stopped(pid, signal) => {
-                let job = unsafe {
-                    JOBS_LIST
-                        .as_mut()
-                        .unwrap()
-                        .getjob_pid(pid)
-                        .expect("Nonexistent job killed by signal! Bug or race condition?")
+                let was_fg = match joblist().getjob_pid(pid) {
+                    Some(job) => job.state() == JobState::FG,
+                    None => continue,
+                };
+                if was_fg {
+                    joblist().set_fg_status(128 + signal as i32);
+                    joblist().add_fg_times(times);
+                }
+                let job = match joblist().getjob_pid(pid) {
+                    Some(job) => job,
+                    None => continue,
                 };
                 job.set_state(JobState::Stop);
-                let msgbuf = b"Job [%d] (%d) stopped by signal %d\n";
-                let msg = format_string_int(msgbuf, job.jid());
-                let msg = format_string_int(&msg[..], job.pid().as_raw());
-                let msg = format_string_int(&msg[..], signal as i32);
-                signal_write_out(&msg[..]);
+                let mut msg = MsgBuf::new();
+                let _ = writeln!(
+                    msg,
+                    "Job [{}] ({}) stopped by signal {}",
+                    job.jid(),
+                    job.pid(),
+                    signal as i32
+                );
+                signal_write_out(msg.as_bytes());
             }
             WaitStatus::Continued(pid) => {
-                let job = unsafe { JOBS_LIST.as_mut().unwrap().getjob_pid(pid) };
-                if job.is_none() {
-                    panic!("Nonexistent job killed by signal! Bug or race condition?")
-                }
-                /* Skeleton handler code: this may need to be reworked or removed
-                 * later. It's here for now because mshref doesn't handle SIGCONT
-                 * from outside the shell, and I want to fix that at some point.
-                 * The correct behavior should be to pretend that the user requested
-                 * CONT of the job in the background via bg, but without printing
-                 * anything. It doesn't make sense to put the job in the fg (what
-                 * if another job is there?), and leaving it in a stopped state
-                 * means that jobs shows incorrect information.
+                /* A SIGCONT that we didn't send ourselves (e.g. `kill -CONT`
+                 * from another terminal). fg and bg update the job state before
+                 * we ever see this, so only stopped jobs need fixing: pretend
+                 * that the user asked for the job to be continued in the
+                 * background via bg, but without printing anything. It doesn't
+                 * make sense to put the job in the fg (what if another job is
+                 * there?), and leaving it in a stopped state means that jobs
+                 * shows incorrect information. A foreground job that is
+                 * continued is left alone, since it never stopped being the
+                 * job that the shell is waiting on.
                  */
+                if let Some(job) = joblist().getjob_pid(pid) {
+                    if job.state() == JobState::Stop {
+                        job.set_state(JobState::BG);
+                    }
+                }
             }
             WaitStatus::StillAlive => {
                 // This was a triumph
@@ -426,4 +856,5 @@ extern "C" fn sigchld_handler(_: c_int) -> () {
             _ => unreachable!(),
         }
     }
+    traps::note(sig);
 }
@@ -0,0 +1,140 @@
+/// The interactive prompt, built from `$PS1` before each line is read. As in
+/// bash, backslash escapes in `$PS1` stand for things that change as the shell
+/// runs: `\w` for the working directory, `\?` for the status of the last
+/// command, and so on.
+use crate::dirs::{current_dir, tilde_abbrev};
+use crate::util::ExpandEnv;
+use crate::vars::vars;
+use crate::{block_job_signals, joblist, restore_signals};
+
+/// The prompt when `$PS1` isn't set, the same one mshref shows
+pub const DEFAULT_PS1: &str = "msh> ";
+
+/// What the escapes in a prompt stand for
+#[derive(Clone, Debug, Default)]
+pub struct PromptInfo {
+    pub cwd: String,  // Already abbreviated with a `~`
+    pub user: String, // \u
+    pub host: String, // \H, and \h up to the first `.`
+    pub status: i32,  // \?
+    pub jobs: usize,  // \j
+    pub root: bool,   // Whether \$ is a `#`
+}
+
+impl PromptInfo {
+    /// Gather everything the prompt could show about the shell right now
+    pub fn current() -> Self {
+        let uid = nix::unistd::getuid();
+        let user = match nix::unistd::User::from_uid(uid) {
+            Ok(Some(user)) => user.name,
+            _ => vars().get("USER").unwrap_or("").to_string(),
+        };
+        let mut buf = [0u8; 256];
+        let host = nix::unistd::gethostname(&mut buf)
+            .ok()
+            .and_then(|name| name.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        let old_blockset = block_job_signals();
+        let jobs = joblist().iter().count();
+        restore_signals(&old_blockset);
+
+        PromptInfo {
+            cwd: tilde_abbrev(&current_dir()),
+            user,
+            host,
+            status: vars().last_status(),
+            jobs,
+            root: uid.is_root(),
+        }
+    }
+}
+
+/// Replace the escapes in `ps1`:
+///   \w  the working directory      \W  its last component
+///   \u  the user name              \h  the host name, up to the first `.`
+///   \H  the full host name         \?  the status of the last command
+///   \j  the number of jobs         \$  `#` for root, `$` for everyone else
+///   \n  a newline                  \e  an escape, for colors
+///   \\  a backslash
+/// `\[` and `\]`, which bash uses to mark non-printing text, are dropped. Any
+/// other backslash is left as it is.
+pub fn expand_prompt(ps1: &str, info: &PromptInfo) -> String {
+    let mut prompt = String::new();
+    let mut chars = ps1.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('w') => prompt.push_str(&info.cwd),
+            Some('W') => prompt.push_str(match info.cwd.rsplit('/').next() {
+                Some("") | None => &info.cwd, // The root directory
+                Some(last) => last,
+            }),
+            Some('u') => prompt.push_str(&info.user),
+            Some('h') => prompt.push_str(info.host.split('.').next().unwrap_or("")),
+            Some('H') => prompt.push_str(&info.host),
+            Some('?') => prompt.push_str(&info.status.to_string()),
+            Some('j') => prompt.push_str(&info.jobs.to_string()),
+            Some('$') => prompt.push(if info.root { '#' } else { '$' }),
+            Some('n') => prompt.push('\n'),
+            Some('e') => prompt.push('\x1b'),
+            Some('\\') => prompt.push('\\'),
+            Some('[') | Some(']') => {}
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+/// The prompt to show before reading the next line
+pub fn prompt() -> String {
+    match vars().get("PS1") {
+        Some(ps1) => expand_prompt(ps1, &PromptInfo::current()),
+        None => DEFAULT_PS1.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_prompt() {
+        let info = PromptInfo {
+            cwd: "~/src/minishell".to_string(),
+            user: "kevin".to_string(),
+            host: "box.example.com".to_string(),
+            status: 1,
+            jobs: 2,
+            root: false,
+        };
+        assert_eq!(expand_prompt("msh> ", &info), "msh> ");
+        assert_eq!(
+            expand_prompt("\\u@\\h:\\w\\$ ", &info),
+            "kevin@box:~/src/minishell$ "
+        );
+        assert_eq!(
+            expand_prompt("[\\?] \\j \\W \\H\\n> ", &info),
+            "[1] 2 minishell box.example.com\n> "
+        );
+        assert_eq!(
+            expand_prompt("\\[\\e[1m\\]\\W\\\\ \\x\\", &info),
+            "\x1b[1mminishell\\ \\x\\"
+        );
+
+        let root = PromptInfo {
+            cwd: "/".to_string(),
+            root: true,
+            ..info
+        };
+        assert_eq!(expand_prompt("\\W\\$", &root), "/#");
+    }
+}
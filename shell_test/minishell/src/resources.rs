@@ -0,0 +1,252 @@
+/// Resource usage and limits: the CPU times reported by `time` and `times`, and
+/// the limits set by `ulimit`.
+/* Notes:
+  `ulimit` doesn't limit the shell itself. The limits are saved here, and each
+  job applies them to itself with setrlimit between fork and execve, so that a
+  `ulimit -t 1` can't get the shell killed.
+
+  The sigchld handler adds up the CPU time of foreground jobs as it reaps them
+  (see JobList::add_fg_times), which is where `time` gets the time of the jobs
+  it runs from.
+*/
+use crate::errors::ShellError;
+use crate::{block_job_signals, joblist, restore_signals};
+use libc::rlim_t;
+use nix::errno::Errno;
+use std::ops::{Add, Sub};
+use std::time::{Duration, Instant};
+
+/// Time spent running in user mode and in the kernel
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+fn timeval_duration(tv: &libc::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
+
+impl CpuTimes {
+    pub fn from_rusage(usage: &libc::rusage) -> Self {
+        CpuTimes {
+            user: timeval_duration(&usage.ru_utime),
+            sys: timeval_duration(&usage.ru_stime),
+        }
+    }
+
+    /// The times of the shell itself (RUSAGE_SELF), or of all the children it
+    /// has waited for (RUSAGE_CHILDREN)
+    fn get(who: libc::c_int) -> Self {
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // getrusage can only fail for a bad `who`
+        unsafe {
+            libc::getrusage(who, usage.as_mut_ptr());
+            CpuTimes::from_rusage(&usage.assume_init())
+        }
+    }
+
+    pub fn shell() -> Self {
+        CpuTimes::get(libc::RUSAGE_SELF)
+    }
+
+    pub fn children() -> Self {
+        CpuTimes::get(libc::RUSAGE_CHILDREN)
+    }
+}
+
+impl Add for CpuTimes {
+    type Output = CpuTimes;
+    fn add(self, other: CpuTimes) -> CpuTimes {
+        CpuTimes {
+            user: self.user + other.user,
+            sys: self.sys + other.sys,
+        }
+    }
+}
+
+impl Sub for CpuTimes {
+    type Output = CpuTimes;
+    fn sub(self, other: CpuTimes) -> CpuTimes {
+        CpuTimes {
+            user: self.user.saturating_sub(other.user),
+            sys: self.sys.saturating_sub(other.sys),
+        }
+    }
+}
+
+/// Format a duration the way bash's `time` and `times` do, e.g. `1m2.345s`
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!(
+        "{}m{}.{:03}s",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Measures a command run with the `time` keyword
+pub struct Timer {
+    start: Instant,
+    shell: CpuTimes,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        let old_blockset = block_job_signals();
+        joblist().take_fg_times();
+        restore_signals(&old_blockset);
+        Timer {
+            start: Instant::now(),
+            shell: CpuTimes::shell(),
+        }
+    }
+
+    /// Report the time taken since start(): the real time, and the CPU time of
+    /// the shell (for builtins) and of the foreground jobs it waited for.
+    pub fn report(self) {
+        let real = self.start.elapsed();
+        let old_blockset = block_job_signals();
+        let jobs = joblist().take_fg_times();
+        restore_signals(&old_blockset);
+        let cpu = CpuTimes::shell() - self.shell + jobs;
+
+        eprintln!(
+            "\nreal\t{}\nuser\t{}\nsys\t{}",
+            format_duration(real),
+            format_duration(cpu.user),
+            format_duration(cpu.sys)
+        );
+    }
+}
+
+/// A resource that `ulimit` can limit
+pub struct Limit {
+    pub flag: char,
+    pub description: &'static str,
+    resource: libc::__rlimit_resource_t,
+    unit: rlim_t, // ulimit's unit, in the kernel's units
+}
+
+pub const LIMITS: [Limit; 3] = [
+    Limit {
+        flag: 't',
+        description: "cpu time (seconds)",
+        resource: libc::RLIMIT_CPU,
+        unit: 1,
+    },
+    Limit {
+        flag: 'v',
+        description: "virtual memory (kbytes)",
+        resource: libc::RLIMIT_AS,
+        unit: 1024,
+    },
+    Limit {
+        flag: 'n',
+        description: "open files",
+        resource: libc::RLIMIT_NOFILE,
+        unit: 1,
+    },
+];
+
+/// The limits that jobs will set for themselves, in the order of LIMITS
+static mut JOB_LIMITS: [Option<rlim_t>; 3] = [None; 3];
+
+fn job_limits() -> &'static mut [Option<rlim_t>; 3] {
+    unsafe { &mut *std::ptr::addr_of_mut!(JOB_LIMITS) }
+}
+
+/// Look up a limit by its ulimit flag
+pub fn find_limit(flag: char) -> Option<usize> {
+    LIMITS.iter().position(|limit| limit.flag == flag)
+}
+
+/// The limits the shell itself runs with, as (soft, hard)
+fn shell_limits(limit: &Limit) -> (rlim_t, rlim_t) {
+    let mut rlim = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    unsafe {
+        libc::getrlimit(limit.resource, &mut rlim);
+    }
+    (rlim.rlim_cur, rlim.rlim_max)
+}
+
+/// The limit that jobs will run with, in ulimit's units. None means unlimited.
+pub fn get_limit(index: usize) -> Option<rlim_t> {
+    let limit = &LIMITS[index];
+    let value = job_limits()[index].unwrap_or_else(|| shell_limits(limit).0);
+    if value == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(value / limit.unit)
+    }
+}
+
+/// Set the limit that jobs will run with, in ulimit's units (None for
+/// unlimited). Fails with the reason if the limit is above the hard limit,
+/// which only root could raise.
+pub fn set_limit(index: usize, value: Option<rlim_t>) -> Result<(), &'static str> {
+    let limit = &LIMITS[index];
+    let value = match value {
+        Some(value) => value.checked_mul(limit.unit).ok_or("limit out of range")?,
+        None => libc::RLIM_INFINITY,
+    };
+    let (_, hard) = shell_limits(limit);
+    let root = nix::unistd::geteuid().is_root();
+    if hard != libc::RLIM_INFINITY && (value == libc::RLIM_INFINITY || value > hard) && !root {
+        return Err(Errno::EPERM.desc());
+    }
+    job_limits()[index] = Some(value);
+    Ok(())
+}
+
+/// Apply the limits set by `ulimit` to this process. Called in a freshly forked
+/// job, before it runs anything.
+pub fn apply_limits() -> Result<(), ShellError> {
+    for (limit, value) in LIMITS.iter().zip(job_limits().iter()) {
+        let value = match value {
+            Some(value) => *value,
+            None => continue,
+        };
+        let (_, hard) = shell_limits(limit);
+        let rlim = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: if value > hard { value } else { hard },
+        };
+        if unsafe { libc::setrlimit(limit.resource, &rlim) } == -1 {
+            return Err(ShellError::Sys("setrlimit", Errno::last()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(3)), "0m0.003s");
+        assert_eq!(format_duration(Duration::from_millis(62_345)), "1m2.345s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "60m0.000s");
+    }
+
+    #[test]
+    fn test_cpu_times() {
+        let a = CpuTimes {
+            user: Duration::from_millis(30),
+            sys: Duration::from_millis(10),
+        };
+        let b = CpuTimes {
+            user: Duration::from_millis(5),
+            sys: Duration::from_millis(20),
+        };
+        assert_eq!((a + b).user, Duration::from_millis(35));
+        // Clock granularity can make the later reading smaller; don't underflow
+        assert_eq!((a - b).sys, Duration::ZERO);
+        assert_eq!((a - b).user, Duration::from_millis(25));
+    }
+}
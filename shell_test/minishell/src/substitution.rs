@@ -0,0 +1,118 @@
+/// Command substitution, `$(command)`, which is replaced by what the command
+/// prints, and process substitution, `<(command)`, which is replaced by a path
+/// that the command's output can be read from while it runs.
+/* Notes:
+  Both run their command in a helper: a subshell forked by spawn_helper(), with
+  its stdout on a pipe. Helpers aren't jobs, and `jobs` doesn't show them, but
+  they're kept in the joblist so that the sigchld handler, which reaps every
+  child, can record their statuses for whoever is waiting on them.
+
+  The path of a process substitution is /dev/fd/N, where N is the shell's end
+  of the pipe. That end stays open until the command it's an argument of is
+  done, so that the command (or a job started for it) inherits it. The helper
+  is forgotten then, and reaped like any other stray child when it finishes.
+*/
+use crate::errors::ShellError;
+use crate::{block_job_signals, joblist, restore_signals, spawn_helper, wait_helper};
+use nix::errno::Errno;
+use nix::unistd::{close, pipe, Pid};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+#[derive(Default)]
+pub struct Substitutions {
+    open: Vec<(RawFd, Pid)>, // The pipes and helpers of process substitutions in use
+    status: Option<i32>,     // The status of the last command substitution
+}
+
+impl Substitutions {
+    pub const fn new() -> Self {
+        Substitutions {
+            open: Vec::new(),
+            status: None,
+        }
+    }
+}
+
+static mut SUBSTITUTIONS: Substitutions = Substitutions::new();
+
+/// Get a handle to the shell's substitutions
+pub fn substitutions() -> &'static mut Substitutions {
+    unsafe { &mut *std::ptr::addr_of_mut!(SUBSTITUTIONS) }
+}
+
+/// Start `command` in a helper, returning the shell's end of the pipe that the
+/// helper prints to, and the helper's pid
+fn start(command: &str) -> Result<(RawFd, Pid), ShellError> {
+    let (read_end, write_end) = pipe().map_err(|_| ShellError::Sys("pipe", Errno::last()))?;
+    let helper = spawn_helper(command, read_end, write_end);
+    let _ = close(write_end);
+    match helper {
+        Ok(pid) => Ok((read_end, pid)),
+        Err(e) => {
+            let _ = close(read_end);
+            Err(e)
+        }
+    }
+}
+
+/// Run `command` in a helper, and return everything that it prints. Its status
+/// is kept for take_status().
+pub fn command_output(command: &str) -> String {
+    let (fd, pid) = match start(command) {
+        Ok(started) => started,
+        Err(e) => {
+            e.report();
+            substitutions().status = Some(e.status());
+            return String::new();
+        }
+    };
+
+    let mut output = Vec::new();
+    let mut pipe = unsafe { File::from_raw_fd(fd) };
+    let _ = pipe.read_to_end(&mut output);
+    drop(pipe);
+    substitutions().status = Some(wait_helper(pid));
+
+    // Arguments can't hold NULs, so they're dropped, as bash does
+    output.retain(|&b| b != 0);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Start `command` in a helper, and return the path of a file that reads what
+/// it prints. The file stays open until close_since() is called.
+pub fn process_output(command: &str) -> String {
+    match start(command) {
+        Ok((fd, pid)) => {
+            substitutions().open.push((fd, pid));
+            format!("/dev/fd/{}", fd)
+        }
+        Err(e) => {
+            e.report();
+            String::new()
+        }
+    }
+}
+
+/// The number of process substitutions open, to pass to close_since() once
+/// the command being expanded is done
+pub fn mark() -> usize {
+    substitutions().open.len()
+}
+
+/// Close the process substitutions opened since mark() returned `mark`, and
+/// forget about their helpers
+pub fn close_since(mark: usize) {
+    let old_blockset = block_job_signals();
+    for (fd, pid) in substitutions().open.drain(mark..) {
+        let _ = close(fd);
+        joblist().remove_helper(pid);
+    }
+    restore_signals(&old_blockset);
+}
+
+/// The status of the last command substitution since the last call, if any
+pub fn take_status() -> Option<i32> {
+    substitutions().status.take()
+}
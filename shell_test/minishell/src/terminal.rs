@@ -0,0 +1,119 @@
+/// Controlling terminal management for interactive use. The shell puts itself
+/// in its own process group, takes the terminal at startup, and hands it to
+/// whichever job is in the foreground, so that programs like vim and less can
+/// read from the terminal without being stopped by SIGTTIN/SIGTTOU.
+/* Notes:
+  If stdin isn't a terminal (e.g. when being driven by sdriver.pl), all of this
+  is switched off and every function here is a no-op, so the shell behaves
+  exactly as it did before it knew about terminals.
+
+  Only the main loop touches the terminal state (never the signal handlers), so
+  unlike JOBS_LIST there is nothing to race against here.
+*/
+use crate::util::install_sighandler;
+use nix::sys::signal::{kill, SigHandler, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{getpgrp, getpid, isatty, setpgid, tcgetpgrp, tcsetpgrp, Pid};
+use std::os::unix::io::RawFd;
+
+const TERMINAL_FD: RawFd = 0;
+
+/// The shell's view of its controlling terminal
+struct Terminal {
+    shell_pgid: Pid,
+    shell_tmodes: Termios,
+}
+
+static mut TERMINAL: Option<Terminal> = None;
+
+fn terminal() -> Option<&'static Terminal> {
+    unsafe { (*std::ptr::addr_of!(TERMINAL)).as_ref() }
+}
+
+/// Take control of the terminal, if stdin is one. Returns true if the shell is
+/// now managing a terminal.
+pub fn init() -> bool {
+    if !isatty(TERMINAL_FD).unwrap_or(false) {
+        return false;
+    }
+
+    // If we were started in the background, wait until we're put in the
+    // foreground before grabbing the terminal. SIGTTIN stops us until then.
+    loop {
+        let pgrp = getpgrp();
+        match tcgetpgrp(TERMINAL_FD) {
+            Ok(fg) if fg == pgrp => break,
+            Ok(_) => {
+                let _ = kill(Pid::from_raw(-pgrp.as_raw()), Signal::SIGTTIN);
+            }
+            Err(_) => return false,
+        }
+    }
+
+    // Once we start handing the terminal out, the shell will be a background
+    // process some of the time, and must not be stopped for touching it.
+    install_sighandler(SigHandler::SigIgn, Signal::SIGTTIN);
+    install_sighandler(SigHandler::SigIgn, Signal::SIGTTOU);
+
+    // Put ourselves in our own process group. A session leader already is the
+    // leader of its process group (and isn't allowed to call setpgid).
+    let shell_pgid = getpid();
+    if getpgrp() != shell_pgid && setpgid(shell_pgid, shell_pgid).is_err() {
+        return false;
+    }
+    if tcsetpgrp(TERMINAL_FD, shell_pgid).is_err() {
+        return false;
+    }
+    let shell_tmodes = match tcgetattr(TERMINAL_FD) {
+        Ok(modes) => modes,
+        Err(_) => return false,
+    };
+
+    unsafe {
+        TERMINAL = Some(Terminal {
+            shell_pgid,
+            shell_tmodes,
+        });
+    }
+    true
+}
+
+/// Undo the signal dispositions set up by init() in a freshly forked child.
+/// Ignored signals stay ignored across execve, so this must happen before it.
+pub fn reset_child_signals() {
+    if terminal().is_some() {
+        install_sighandler(SigHandler::SigDfl, Signal::SIGTTIN);
+        install_sighandler(SigHandler::SigDfl, Signal::SIGTTOU);
+    }
+}
+
+/// Make the process group `pgid` the foreground process group of the terminal,
+/// restoring the terminal modes that the job had when it was last stopped.
+pub fn give_to(pgid: Pid, tmodes: Option<&Termios>) {
+    if terminal().is_none() {
+        return;
+    }
+    let _ = tcsetpgrp(TERMINAL_FD, pgid);
+    if let Some(tmodes) = tmodes {
+        let _ = tcsetattr(TERMINAL_FD, SetArg::TCSADRAIN, tmodes);
+    }
+}
+
+/// Take the terminal back from a foreground job and restore the shell's own
+/// terminal modes. Returns the modes the job left the terminal in, so that they
+/// can be restored if the job is later continued in the foreground.
+pub fn reclaim() -> Option<Termios> {
+    let term = terminal()?;
+    let _ = tcsetpgrp(TERMINAL_FD, term.shell_pgid);
+    let job_tmodes = tcgetattr(TERMINAL_FD).ok();
+    let _ = tcsetattr(TERMINAL_FD, SetArg::TCSADRAIN, &term.shell_tmodes);
+    job_tmodes
+}
+
+/// Forget about the terminal, leaving it to the parent shell. Used by
+/// subshells, which don't do job control of their own.
+pub fn release() {
+    unsafe {
+        TERMINAL = None;
+    }
+}
@@ -0,0 +1,220 @@
+/// Traps: commands set with `trap 'cmd' SIG ...` to run when the shell gets a
+/// signal, or with `trap 'cmd' EXIT` to run as it exits.
+/* Notes:
+  A trapped command can't run inside the signal handler, which mustn't do
+  anything that allocates. The handler only marks the signal as pending, and the
+  main loop runs the commands for whatever is pending between commands and
+  before each prompt, through run_pending().
+
+  SIGINT, SIGTSTP and SIGQUIT (when there's job control) and SIGCHLD already
+  have handlers of the shell's own, which have work to do whatever the user
+  traps. Trapping one of these only makes that handler mark it as pending too;
+  its disposition never changes. Any other signal gets trap_handler, or is
+  ignored for `trap '' SIG`.
+
+  Jobs start with no traps, and with every trapped signal back at its default,
+  so that nothing the shell traps or ignores leaks into the programs it runs.
+*/
+use crate::util::{install_sighandler, parse_signal, ExpandEnv};
+use crate::vars::vars;
+use crate::{eval, job_control, sigchld_handler, sigint_handler, sigquit_handler, sigtstp_handler};
+use libc::c_int;
+use nix::sys::signal::{SigHandler, Signal};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// One more than the highest signal number
+const NSIG: usize = 65;
+
+/// The "signal number" of the EXIT trap, as in bash
+pub const EXIT: i32 = 0;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNSET: AtomicBool = AtomicBool::new(false);
+
+/// The signals with a trap set, for the handlers to check. Indexed by number.
+static TRAPPED: [AtomicBool; NSIG] = [UNSET; NSIG];
+
+/// The trapped signals that have arrived, but whose traps haven't run yet
+static PENDING: [AtomicBool; NSIG] = [UNSET; NSIG];
+static ANY_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Whether `signal` has a trap set. Safe to call from a signal handler.
+pub fn trapped(signal: c_int) -> bool {
+    let index = signal as usize;
+    index < NSIG && TRAPPED[index].load(Ordering::SeqCst)
+}
+
+/// Note that `signal` has arrived, if it's trapped. Safe to call from a signal
+/// handler.
+pub fn note(signal: c_int) {
+    if trapped(signal) {
+        PENDING[signal as usize].store(true, Ordering::SeqCst);
+        ANY_PENDING.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The handler for trapped signals that the shell doesn't handle itself
+extern "C" fn trap_handler(signal: c_int) {
+    note(signal);
+}
+
+/// The trapped commands, by signal number. An empty command means the signal
+/// is ignored.
+#[derive(Default)]
+pub struct Traps {
+    commands: BTreeMap<i32, String>,
+    running: bool, // Whether run_pending() is running traps already
+}
+
+impl Traps {
+    pub const fn new() -> Self {
+        Traps {
+            commands: BTreeMap::new(),
+            running: false,
+        }
+    }
+
+    pub fn get(&self, signal: i32) -> Option<&str> {
+        self.commands.get(&signal).map(|x| &x[..])
+    }
+
+    /// The traps, in signal number order, with EXIT first
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &String)> {
+        self.commands
+            .iter()
+            .map(|(signal, command)| (*signal, command))
+    }
+}
+
+static mut TRAPS: Traps = Traps::new();
+
+/// Get a handle to the shell's traps
+pub fn traps() -> &'static mut Traps {
+    unsafe { &mut *std::ptr::addr_of_mut!(TRAPS) }
+}
+
+/// Parse the name of something that can be trapped: `EXIT` (or `0`), or a
+/// signal, by name or number. Returns its number.
+pub fn parse_trap(name: &str) -> Option<i32> {
+    if name == "0" || name.eq_ignore_ascii_case("EXIT") {
+        return Some(EXIT);
+    }
+    match parse_signal(name)? {
+        // These can't be caught or ignored
+        Signal::SIGKILL | Signal::SIGSTOP => None,
+        signal => Some(signal as i32),
+    }
+}
+
+/// The name of a trap, as `trap -p` shows it
+pub fn trap_name(signal: i32) -> &'static str {
+    match Signal::try_from(signal) {
+        Ok(signal) => signal.as_str(),
+        Err(_) => "EXIT",
+    }
+}
+
+/// What `signal` is handled by when it isn't trapped
+fn default_handler(signal: Signal) -> SigHandler {
+    match signal {
+        Signal::SIGCHLD => SigHandler::Handler(sigchld_handler),
+        Signal::SIGINT if job_control() => SigHandler::Handler(sigint_handler),
+        Signal::SIGTSTP if job_control() => SigHandler::Handler(sigtstp_handler),
+        Signal::SIGQUIT if job_control() => SigHandler::Handler(sigquit_handler),
+        _ => SigHandler::SigDfl,
+    }
+}
+
+/// Run `command` when `signal` arrives (or as the shell exits, for EXIT), or
+/// ignore the signal if the command is empty
+pub fn set_trap(signal: i32, command: &str) {
+    traps().commands.insert(signal, command.to_string());
+    if let Ok(signal) = Signal::try_from(signal) {
+        if let SigHandler::SigDfl = default_handler(signal) {
+            let handler = if command.is_empty() {
+                SigHandler::SigIgn
+            } else {
+                SigHandler::Handler(trap_handler)
+            };
+            install_sighandler(handler, signal);
+        }
+        TRAPPED[signal as usize].store(true, Ordering::SeqCst);
+    }
+}
+
+/// Remove the trap on `signal`, putting back its usual handling
+pub fn reset_trap(signal: i32) {
+    traps().commands.remove(&signal);
+    if let Ok(signal) = Signal::try_from(signal) {
+        TRAPPED[signal as usize].store(false, Ordering::SeqCst);
+        PENDING[signal as usize].store(false, Ordering::SeqCst);
+        install_sighandler(default_handler(signal), signal);
+    }
+}
+
+/// Remove every trap. Called in each new job before it runs anything.
+pub fn reset_for_child() {
+    let signals: Vec<i32> = traps().commands.keys().copied().collect();
+    for signal in signals {
+        reset_trap(signal);
+    }
+}
+
+/// Run a trapped command, leaving `$?` as it was
+fn run(command: &str) {
+    let status = vars().last_status();
+    eval(command);
+    vars().set_last_status(status);
+}
+
+/// Run the traps for the signals that have arrived since the last call, in
+/// signal number order
+pub fn run_pending() {
+    if traps().running || !ANY_PENDING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    traps().running = true;
+    for (signal, pending) in PENDING.iter().enumerate() {
+        if !pending.swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        if let Some(command) = traps().get(signal as i32) {
+            if !command.is_empty() {
+                let command = command.to_string();
+                run(&command);
+            }
+        }
+    }
+    traps().running = false;
+}
+
+/// Run the EXIT trap, if there is one. It's removed first, so that an `exit`
+/// inside it doesn't run it again.
+pub fn run_exit_trap() {
+    if let Some(command) = traps().commands.remove(&EXIT) {
+        run(&command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trap() {
+        assert_eq!(parse_trap("EXIT"), Some(EXIT));
+        assert_eq!(parse_trap("exit"), Some(EXIT));
+        assert_eq!(parse_trap("0"), Some(EXIT));
+        assert_eq!(parse_trap("INT"), Some(libc::SIGINT));
+        assert_eq!(parse_trap("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(parse_trap("10"), Some(libc::SIGUSR1));
+        assert_eq!(parse_trap("KILL"), None);
+        assert_eq!(parse_trap("STOP"), None);
+        assert_eq!(parse_trap("BOGUS"), None);
+
+        assert_eq!(trap_name(EXIT), "EXIT");
+        assert_eq!(trap_name(libc::SIGINT), "SIGINT");
+    }
+}
@@ -1,10 +1,13 @@
 use nix::errno::{errno, from_i32};
 use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::convert::TryFrom;
 use std::ffi::CString;
 use std::process::exit;
 use std::vec::Vec;
 
-use crate::jobs::JobState;
+use crate::glob::{brace_expand, glob, has_magic, GlobOptions};
+use crate::jobs::JobSpec;
+use nix::unistd::Pid;
 
 pub const MAX_LINE_SIZE: usize = 1024;
 pub const MAX_NUM_ARGS: usize = 128;
@@ -19,46 +22,91 @@ pub fn unix_error(msg: &str) {
 // If a signal arrives during a syscall, we want to let the system automatically
 // restart the syscall instead of automatically failing. SA_RESTART will do this
 // for us, see `man 7 signal` for a discussion of this behavior.
-pub fn install_sighandler(handler: SigHandler, sig: Signal) -> () {
+pub fn install_sighandler(handler: SigHandler, sig: Signal) {
     let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
     unsafe {
         sigaction(sig, &action).expect("Could not set signal handler with sigaction");
     }
 }
 
-fn usage() -> () {
-    println!("Usage: shell [-hvp]\n");
+fn usage() {
+    println!("Usage: shell [-hvp] [-c command [name [args...]] | script [args...]]\n");
     println!("   -h   print this message\n");
-    println!("   -v   print additional diagnostic information\n");
+    println!("   -v   print each command line before running it\n");
     println!("   -p   do not emit a command prompt\n");
+    println!("   -c   run the given command instead of reading from stdin\n");
 }
 
-/// Parse shell arguments. The only flags that are passed to the shell right now
-/// alter its global behavior. Reflect the requested behavior changes by passing
-/// a tuple of (verbose, emit_prompt) booleans back to the caller.
-pub fn parse_shell_args() -> (bool, bool) {
-    let mut verbose = false;
-    let mut emit_prompt = true;
+/// Where the shell reads its commands from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Stdin,
+    Command(String), // -c
+    Script(String),  // The path of a script file
+}
+
+/// The behavior requested on the command line
+pub struct ShellArgs {
+    pub verbose: bool,
+    pub emit_prompt: bool,
+    pub input: Input,
+    pub arg0: String,        // $0
+    pub params: Vec<String>, // $1, $2, ...
+}
+
+/// Parse shell arguments. Flags come first, and alter the global behavior of
+/// the shell. The first argument that isn't a flag is a script to run, and the
+/// rest are its positional parameters.
+pub fn parse_shell_args() -> ShellArgs {
     let args: Vec<String> = std::env::args().collect();
-    for arg in args[1..].iter().map(|x| &x[..]) {
-        match arg {
+    let mut shell_args = ShellArgs {
+        verbose: false,
+        emit_prompt: true,
+        input: Input::Stdin,
+        arg0: args[0].clone(),
+        params: Vec::new(),
+    };
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match &arg[..] {
             "-h" => {
                 usage();
                 std::process::exit(0) // User requested help: exit success
             }
             "-v" => {
-                verbose = true;
+                shell_args.verbose = true;
             }
             "-p" => {
-                emit_prompt = false;
+                shell_args.emit_prompt = false;
             }
-            _ => {
+            "-c" => match rest.next() {
+                Some(command) => {
+                    shell_args.input = Input::Command(command.clone());
+                    // Like sh -c, the next argument is $0
+                    if let Some(arg0) = rest.next() {
+                        shell_args.arg0 = arg0.clone();
+                    }
+                    break;
+                }
+                None => {
+                    usage();
+                    std::process::exit(1)
+                }
+            },
+            _ if arg.starts_with('-') => {
                 usage();
                 std::process::exit(1) // Bad input: exit with error
             }
+            _ => {
+                shell_args.input = Input::Script(arg.clone());
+                shell_args.arg0 = arg.clone();
+                break;
+            }
         }
     }
-    (verbose, emit_prompt)
+    shell_args.params = rest.cloned().collect();
+    shell_args
 }
 
 /// Help manage the args for execve syscall
@@ -66,9 +114,13 @@ pub fn parse_shell_args() -> (bool, bool) {
 /// The execve syscall needs some odd types, and formats that aren't necessarily
 /// provided natively by Rust. This helper function creates the correct forms
 /// for execve() so that the return value just has to be casted and passed in.
-pub fn cast_execve_args(args: Vec<String>) -> (Vec<CString>, Vec<CString>) {
+pub fn cast_execve_args(
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+) -> (Vec<CString>, Vec<CString>) {
     let argv: Vec<CString> = args.into_iter().map(|x| CString::new(x).unwrap()).collect();
-    let env: Vec<CString> = std::env::vars()
+    let env: Vec<CString> = env
+        .into_iter()
         .map(|(x, y)| {
             // Manually place vars in the expected form
             let kvps = format!("{}={}", x, y);
@@ -76,107 +128,89 @@ pub fn cast_execve_args(args: Vec<String>) -> (Vec<CString>, Vec<CString>) {
         })
         .collect();
 
-    return (argv, env);
+    (argv, env)
 }
 
-const PANIC_STR: &str = "Handler write failed!";
+/// Safely write a message to stdout from a signal handler. Use a MsgBuf to
+/// format the message without allocating.
+pub fn signal_write_out(msg: &[u8]) {
+    signal_write(1, msg);
+}
 
-/// Safely write a message to stdout from a signal handler. See the format_string_int
-/// and format_string_str functions for ways to generate this buffer without allocation
-pub extern "C" fn signal_write_out(msg: &[u8]) {
-    const STDERR: std::os::unix::io::RawFd = 1;
-    let nwritten = nix::unistd::write(STDERR, msg).unwrap();
-    if nwritten != msg.len() {
-        panic!(PANIC_STR);
-    }
+/// Safely write a message to stderr from a signal handler, like signal_write_out()
+pub fn signal_write_err(msg: &[u8]) {
+    signal_write(2, msg);
 }
 
-/// Safely write a message to stderr from a signal handler. See the format_string_int
-/// and format_string_str functions for ways to generate this buffer without allocation
-pub extern "C" fn signal_write_err(msg: &[u8]) {
-    const STDERR: std::os::unix::io::RawFd = 2;
-    let nwritten = nix::unistd::write(STDERR, msg).unwrap();
-    if nwritten != msg.len() {
-        panic!(PANIC_STR);
+/// Write msg to fd. A signal handler has no way to report a failed write, so
+/// errors are ignored.
+fn signal_write(fd: std::os::unix::io::RawFd, mut msg: &[u8]) {
+    while !msg.is_empty() {
+        match nix::unistd::write(fd, msg) {
+            Ok(n) => msg = &msg[n..],
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(_) => return,
+        }
     }
 }
 
 pub const MSGBUF_LEN: usize = 256;
-/// A custom string formatting function to allow simple string formatting without
-/// needing heap allocations. Takes in a buffer of MAXLENGTH and replaces all instances
-/// of %d with integer representations.
-pub fn format_string_int(buf: &[u8], x: i32) -> [u8; MSGBUF_LEN] {
-    let mut numbuf = [0u8; 16];
-    let mut x = x;
 
-    // Compute the base-10 representation of the number
-    let mut j = 15;
-    while x >= 10 {
-        numbuf[j] = (x % 10) as u8 + '0' as u8;
-        x = x / 10;
-        j -= 1;
-        if j == 0 {
-            break;
-        }
-    }
-    numbuf[j] = x as u8 + '0' as u8;
+/// A message formatted on the stack, for signal handlers, which mustn't
+/// allocate. It's a fmt::Write, so `write!` and `writeln!` can fill it in with
+/// anything that implements Display:
+///
+///     let mut msg = MsgBuf::new();
+///     let _ = writeln!(msg, "Job [{}] ({}) stopped by signal {}", jid, pid, sig);
+///     signal_write_out(msg.as_bytes());
+///
+/// A message longer than MSGBUF_LEN bytes is cut short, at a character
+/// boundary, and the write that overflowed returns an error.
+pub struct MsgBuf {
+    buf: [u8; MSGBUF_LEN],
+    len: usize,
+}
 
-    // Compute the correct representation with no null bytes
-    let mut offset = 0;
-    for i in 0..16 {
-        if numbuf[i] != 0 {
-            offset = i;
-            break;
+impl MsgBuf {
+    pub const fn new() -> Self {
+        MsgBuf {
+            buf: [0; MSGBUF_LEN],
+            len: 0,
         }
     }
-    let mut localcopy = [0u8; MSGBUF_LEN];
 
-    for i in 0..std::cmp::min(buf.len(), MSGBUF_LEN) {
-        localcopy[i] = buf[i];
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
     }
 
-    // Replace the first %d with %s
-    for i in 0..buf.len() {
-        if localcopy[i] == b'%' && localcopy[i + 1] == b'd' {
-            localcopy[i + 1] = b's';
-            break;
-        }
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in
+        std::str::from_utf8(self.as_bytes()).unwrap_or("")
     }
-    format_string_str(&localcopy[..], &numbuf[offset..])
 }
 
-/// A custom string formatting function to allow simple string formatting without
-/// needing heap allocations. Takes in a buffer of MAXLENGTH and replaces all instances
-/// of %s with string representations.
-pub fn format_string_str(buf: &[u8], x: &[u8]) -> [u8; MSGBUF_LEN] {
-    let mut localcopy = [0; MSGBUF_LEN];
-    let mut already_done = false;
-
-    let mut from = 0;
-    let mut to = 0;
+impl Default for MsgBuf {
+    fn default() -> Self {
+        MsgBuf::new()
+    }
+}
 
-    loop {
-        // Write number into buffer using offset
-        if from + 1 < buf.len() && buf[from] == b'%' && buf[from + 1] == b's' && !already_done {
-            for j in 0..x.len() {
-                localcopy[to] = x[j];
-                to += 1;
-            }
-            from += 2;
-            already_done = true;
+impl std::fmt::Write for MsgBuf {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let space = MSGBUF_LEN - self.len;
+        let mut n = std::cmp::min(s.len(), space);
+        while !s.is_char_boundary(n) {
+            n -= 1;
         }
-        if to >= MSGBUF_LEN || from >= buf.len() {
-            break;
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            Err(std::fmt::Error)
+        } else {
+            Ok(())
         }
-        localcopy[to] = buf[from];
-        to += 1;
-        from += 1;
     }
-    localcopy
 }
-/// The result of an attempt to parse a command line.
-#[derive(Clone, Debug, PartialEq)]
-pub struct ParseResult(pub JobState, pub Vec<String>);
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ParseFailReason {
@@ -184,70 +218,559 @@ pub enum ParseFailReason {
     EmptyLine,
     Unmatched(char),
     Invalid(char),
+    NoMatch(String), // A pattern that matched nothing, with failglob set
+    UnexpectedEnd,
     Other(String),
 }
-/// Eats a token and returns the token along with the remainder of buf
-/// Tokens are split on first whitespace or next matching single quote.
-fn chomp_tok(buf: &str) -> Result<(&str, &str), ParseFailReason> {
-    let nows = buf.trim();
-    let delim_char = match nows.chars().next() {
-        Some('\'') => '\'',
-        Some(_) => ' ',
-        None => return Err(ParseFailReason::EmptyArg),
-    };
 
-    let newbuf = if delim_char == '\'' {
-        &nows[1..] // Convenience def! Lets us avoid the initial quote
-    } else {
-        nows
-    };
-    let delim_index = newbuf.find(delim_char);
-
-    // Somewhat ugly nested matches to deal with four different cases
-    match delim_char {
-        '\'' => match delim_index {
-            Some(i) => {
-                let (tok, rest) = newbuf.split_at(i);
-                Ok((tok, &rest[1..]))
+impl ParseFailReason {
+    /// Whether the line ended in the middle of a command, with a quote, group
+    /// or subshell left open, so that the lines after it could complete it
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseFailReason::Unmatched(_) | ParseFailReason::UnexpectedEnd
+        )
+    }
+}
+
+impl std::fmt::Display for ParseFailReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseFailReason::EmptyArg => write!(f, "missing argument"),
+            ParseFailReason::EmptyLine => write!(f, "nothing to run"),
+            ParseFailReason::Unmatched(c) => write!(f, "unmatched `{}'", c),
+            ParseFailReason::Invalid(c) => write!(f, "unexpected `{}'", c),
+            ParseFailReason::NoMatch(pattern) => write!(f, "no match: {}", pattern),
+            ParseFailReason::UnexpectedEnd => write!(f, "unexpected end of line"),
+            ParseFailReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+/// A `NAME=value` assignment, after expansion
+pub type Assignment = (String, String);
+
+/// The shell state that command lines are expanded against: the values of
+/// variables (`$NAME`), the exit status of the last command (`$?`), and the
+/// commands run by substitutions.
+pub trait ExpandEnv {
+    fn var(&self, name: &str) -> Option<String>;
+    fn last_status(&self) -> i32;
+    /// The positional parameters `$1`, `$2`, ..., which `"$@"` expands to
+    fn params(&self) -> Vec<String>;
+    /// Run the command of a `$(command)`, and return what it printed
+    fn command_output(&self, command: &str) -> String;
+    /// Start the command of a `<(command)`, and return a path that its output
+    /// can be read from
+    fn process_output(&self, command: &str) -> String;
+    /// What to do with patterns that don't match anything
+    fn glob_options(&self) -> GlobOptions {
+        GlobOptions::default()
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Scan forward from chars[i] (just past an opening quote) to the matching
+/// closing quote, returning the index of the closing quote. Double quotes may
+/// contain backslash escapes, `${...}` and `$(...)`, which can contain quotes of
+/// their own.
+pub fn skip_quoted(chars: &[char], mut i: usize, quote: char) -> Result<usize, ParseFailReason> {
+    while i < chars.len() {
+        match chars[i] {
+            c if c == quote => return Ok(i),
+            '\\' if quote == '"' => i += 1,
+            '$' if quote == '"' && chars.get(i + 1) == Some(&'{') => {
+                i = skip_braces(chars, i + 2)?;
+            }
+            '$' if quote == '"' && chars.get(i + 1) == Some(&'(') => {
+                i = skip_parens(chars, i + 2)?;
             }
-            None => Err(ParseFailReason::Unmatched('\'')),
-        },
-        ' ' => match delim_index {
-            Some(i) => Ok(newbuf.split_at(i)),
-            None => Ok((newbuf, "")),
-        },
-        _ => unreachable!(),
+            _ => {}
+        }
+        i += 1;
     }
+    Err(ParseFailReason::Unmatched(quote))
 }
 
-/// Parse the command line and build an argv array
-pub fn parseline(buf: &str) -> Result<ParseResult, ParseFailReason> {
-    let mut argv: Vec<String> = Vec::new();
-    let mut remainder = buf;
+/// Scan forward from chars[i] (just past a `${`) to the matching `}`, returning
+/// its index.
+pub fn skip_braces(chars: &[char], mut i: usize) -> Result<usize, ParseFailReason> {
+    while i < chars.len() {
+        match chars[i] {
+            '}' => return Ok(i),
+            '\\' => i += 1,
+            '\'' | '"' => i = skip_quoted(chars, i + 1, chars[i])?,
+            '$' if chars.get(i + 1) == Some(&'{') => i = skip_braces(chars, i + 2)?,
+            '$' if chars.get(i + 1) == Some(&'(') => i = skip_parens(chars, i + 2)?,
+            _ => {}
+        }
+        i += 1;
+    }
+    Err(ParseFailReason::Unmatched('{'))
+}
+
+/// Scan forward from chars[i] (just past a `$(` or `<(`) to the matching `)`,
+/// returning its index. The command inside can have parentheses, quotes and
+/// substitutions of its own.
+pub fn skip_parens(chars: &[char], mut i: usize) -> Result<usize, ParseFailReason> {
+    let mut depth = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Ok(i),
+            ')' => depth -= 1,
+            '\\' => i += 1,
+            '\'' | '"' => i = skip_quoted(chars, i + 1, chars[i])?,
+            '$' if chars.get(i + 1) == Some(&'{') => i = skip_braces(chars, i + 2)?,
+            _ => {}
+        }
+        i += 1;
+    }
+    Err(ParseFailReason::Unmatched('('))
+}
+
+/// A word or an operator from a command line, along with the byte range of the
+/// line that it came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The operators that separate commands, longest first. A newline ends a
+/// command just like `;` does.
+pub const OPERATORS: &[&str] = &["&&", "||", ";", "&", "|", "(", ")", "\n"];
+
+/// Split a command line into words and operators, without expanding anything.
+/// Words are split on unquoted whitespace, but quotes, backslash escapes,
+/// `${...}`, `$(...)` and `<(...)` are kept in the word as they were typed, so
+/// that expand_word() can interpret them. The unquoted operators in OPERATORS
+/// are always tokens of their own, and an unquoted `#` at the start of a word
+/// begins a comment that runs to the end of the line.
+pub fn lex(line: &str) -> Result<Vec<Token>, ParseFailReason> {
+    let chars: Vec<char> = line.chars().collect();
+    // offsets[i] is the byte offset of chars[i], with an entry for the end too
+    let offsets: Vec<usize> = line
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(line.len()))
+        .collect();
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..std::cmp::min(i + 2, chars.len())].iter().collect();
+        let op = OPERATORS.iter().find(|op| rest.starts_with(*op));
+        let end = match c {
+            _ if c == ' ' || c == '\t' || op.is_some() => {
+                if let Some(start) = word_start.take() {
+                    tokens.push(Token {
+                        text: line[offsets[start]..offsets[i]].to_string(),
+                        start: offsets[start],
+                        end: offsets[i],
+                    });
+                }
+                if let Some(op) = op {
+                    let len = op.chars().count();
+                    tokens.push(Token {
+                        text: op.to_string(),
+                        start: offsets[i],
+                        end: offsets[i + len],
+                    });
+                    i += len;
+                } else {
+                    i += 1;
+                }
+                continue;
+            }
+            '#' if word_start.is_none() => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '\\' => std::cmp::min(i + 1, chars.len() - 1),
+            '\'' | '"' => skip_quoted(&chars, i + 1, c)?,
+            '$' if chars.get(i + 1) == Some(&'{') => skip_braces(&chars, i + 2)?,
+            '$' | '<' if chars.get(i + 1) == Some(&'(') => skip_parens(&chars, i + 2)?,
+            _ => i,
+        };
+        word_start.get_or_insert(i);
+        i = end + 1;
+    }
+
+    if let Some(start) = word_start {
+        let end = offsets[std::cmp::min(i, chars.len())];
+        tokens.push(Token {
+            text: line[offsets[start]..end].to_string(),
+            start: offsets[start],
+            end,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Performs the expansions on a single word, accumulating the resulting fields.
+/// Text that comes from an unquoted expansion is split into separate fields on
+/// whitespace; everything else is appended to the current field. Each field is
+/// also kept as a glob pattern, in which the quoted characters are escaped.
+struct Expander<'a> {
+    env: &'a dyn ExpandEnv,
+    fields: Vec<(String, String)>, // Each field, and its pattern
+    field: String,
+    pattern: String,
+    // Whether the current field exists even if it's empty, e.g. because of ""
+    field_started: bool,
+    // Whether unquoted whitespace splits fields, as in the word of ${X:-a b}
+    split_literals: bool,
+    // Whether unquoted expansions are split at all. They aren't in assignments.
+    split_fields: bool,
+}
 
-    loop {
-        match chomp_tok(remainder) {
-            Ok((tok, rem)) => {
-                argv.push(tok.to_string());
-                remainder = rem;
+impl<'a> Expander<'a> {
+    fn new(env: &'a dyn ExpandEnv, split_fields: bool) -> Self {
+        Expander {
+            env,
+            fields: Vec::new(),
+            field: String::new(),
+            pattern: String::new(),
+            field_started: false,
+            split_literals: false,
+            split_fields,
+        }
+    }
+
+    /// Append quoted text, which matches only itself in a pattern
+    fn push_str(&mut self, s: &str) {
+        self.field.push_str(s);
+        for c in s.chars() {
+            if "*?[]\\".contains(c) {
+                self.pattern.push('\\');
             }
-            Err(ParseFailReason::EmptyArg) => break,
-            Err(x) => return Err(x),
+            self.pattern.push(c);
+        }
+        self.field_started = true;
+    }
+
+    /// Append an unquoted character, which may be special in a pattern
+    fn push_unquoted(&mut self, c: char) {
+        self.field.push(c);
+        if c == '\\' {
+            self.pattern.push('\\');
         }
+        self.pattern.push(c);
+        self.field_started = true;
     }
 
-    if argv.is_empty() {
-        return Err(ParseFailReason::EmptyLine);
+    fn push_split(&mut self, s: &str) {
+        if !self.split_fields {
+            return self.push_str(s);
+        }
+        for c in s.chars() {
+            if c == ' ' || c == '\t' || c == '\n' {
+                self.end_field();
+            } else {
+                self.push_unquoted(c);
+            }
+        }
     }
 
-    // argv cannot be empty here, so last is always a Some
-    let lastarg = argv.last().unwrap();
-    if lastarg == "&" {
-        argv.pop(); // Clear out the &--it's not an arg to the prog, it's to the shell
-        Ok(ParseResult(JobState::BG, argv))
-    } else {
-        Ok(ParseResult(JobState::FG, argv))
+    fn end_field(&mut self) {
+        if self.field_started {
+            let field = std::mem::take(&mut self.field);
+            self.fields.push((field, std::mem::take(&mut self.pattern)));
+            self.field_started = false;
+        }
+    }
+
+    /// Expand a `~` or `~user` prefix, returning None if it can't be expanded
+    fn tilde(&self, user: &str) -> Option<String> {
+        if user.is_empty() {
+            return self.env.var("HOME");
+        }
+        let user = nix::unistd::User::from_name(user).ok()??;
+        user.dir.to_str().map(|x| x.to_string())
+    }
+
+    /// Expand the parameter or command substitution starting at chars[i] (just
+    /// past a `$`). Returns the index just past it, or None if this isn't a
+    /// parameter at all (e.g. a lone `$`), in which case the `$` is literal.
+    fn parameter(
+        &mut self,
+        chars: &[char],
+        i: usize,
+        quoted: bool,
+    ) -> Result<Option<usize>, ParseFailReason> {
+        let (value, next) = match chars.get(i) {
+            Some('{') => return self.braced_parameter(chars, i + 1, quoted).map(Some),
+            Some('(') => {
+                let close = skip_parens(chars, i + 1)?;
+                let command: String = chars[i + 1..close].iter().collect();
+                // Like any shell, drop the trailing newlines of the output
+                let mut output = self.env.command_output(&command);
+                output.truncate(output.trim_end_matches('\n').len());
+                (Some(output), close + 1)
+            }
+            Some('?') => (Some(self.env.last_status().to_string()), i + 1),
+            Some('$') => (Some(std::process::id().to_string()), i + 1),
+            Some('@') if quoted => {
+                self.quoted_params();
+                return Ok(Some(i + 1));
+            }
+            Some(&c) if c.is_ascii_digit() || c == '@' || c == '*' || c == '#' => {
+                (self.env.var(&c.to_string()), i + 1)
+            }
+            Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..].iter().take_while(|&&c| is_name_char(c)).count();
+                let name: String = chars[i..i + len].iter().collect();
+                (self.env.var(&name), i + len)
+            }
+            _ => return Ok(None),
+        };
+
+        let value = value.unwrap_or_default();
+        if quoted {
+            self.push_str(&value);
+        } else {
+            self.push_split(&value);
+        }
+        Ok(Some(next))
+    }
+
+    /// Expand `"$@"`: each positional parameter becomes a field of its own, with
+    /// any text before and after the `$@` joined to the first and last ones.
+    fn quoted_params(&mut self) {
+        for (n, param) in self.env.params().iter().enumerate() {
+            if n > 0 {
+                self.end_field();
+            }
+            self.push_str(param);
+        }
+    }
+
+    /// Expand `${NAME}`, `${NAME-word}` or `${NAME:-word}` starting at chars[i]
+    /// (just past the `{`), returning the index just past the closing brace.
+    fn braced_parameter(
+        &mut self,
+        chars: &[char],
+        i: usize,
+        quoted: bool,
+    ) -> Result<usize, ParseFailReason> {
+        let close = skip_braces(chars, i)?;
+        let len = match chars.get(i) {
+            Some(&c) if c.is_ascii_digit() => chars[i..close]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count(),
+            Some(&c) if "?@*#$".contains(c) => 1,
+            _ => chars[i..close]
+                .iter()
+                .take_while(|&&c| is_name_char(c))
+                .count(),
+        };
+        if len == 0 {
+            return Err(ParseFailReason::Invalid(chars[i.min(close)]));
+        }
+        let name: String = chars[i..i + len].iter().collect();
+        let value = match &name[..] {
+            "?" => Some(self.env.last_status().to_string()),
+            "$" => Some(std::process::id().to_string()),
+            _ => self.env.var(&name),
+        };
+
+        // The default word, if any, is only expanded if it's going to be used
+        let op = &chars[i + len..close];
+        let default = match op {
+            [] => None,
+            [':', '-', word @ ..] => Some((word, value.as_ref().is_none_or(|v| v.is_empty()))),
+            ['-', word @ ..] => Some((word, value.is_none())),
+            [c, ..] => return Err(ParseFailReason::Invalid(*c)),
+        };
+
+        match default {
+            Some((word, true)) => {
+                let outer = self.split_literals;
+                self.split_literals = !quoted;
+                self.expand(word, quoted)?;
+                self.split_literals = outer;
+            }
+            _ => {
+                let value = value.unwrap_or_default();
+                if quoted {
+                    self.push_str(&value);
+                } else {
+                    self.push_split(&value);
+                }
+            }
+        }
+        Ok(close + 1)
+    }
+
+    /// Expand the characters of a word. `quoted` is true when the characters
+    /// are inside double quotes, where only `$` and some backslashes are special.
+    fn expand(&mut self, chars: &[char], quoted: bool) -> Result<(), ParseFailReason> {
+        let mut i = 0;
+
+        // Tilde expansion only happens on an unquoted ~ at the start of a word
+        if !quoted && chars.first() == Some(&'~') {
+            let len = chars.iter().take_while(|&&c| c != '/').count();
+            let user: String = chars[1..len].iter().collect();
+            if user
+                .chars()
+                .all(|c| is_name_char(c) || c == '.' || c == '-')
+            {
+                if let Some(home) = self.tilde(&user) {
+                    self.push_str(&home);
+                    i = len;
+                }
+            }
+        }
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\\' if quoted => {
+                    // Inside double quotes, backslash only escapes a few characters
+                    match chars.get(i + 1) {
+                        Some(&next) if "$`\"\\\n".contains(next) => {
+                            self.push_str(&next.to_string());
+                            i += 2;
+                        }
+                        _ => {
+                            self.push_str("\\");
+                            i += 1;
+                        }
+                    }
+                }
+                '\\' => {
+                    match chars.get(i + 1) {
+                        Some(&next) => self.push_str(&next.to_string()),
+                        None => self.push_str("\\"), // Nothing left to escape
+                    }
+                    i += 2;
+                }
+                '\'' if !quoted => {
+                    let close = skip_quoted(chars, i + 1, '\'')?;
+                    let literal: String = chars[i + 1..close].iter().collect();
+                    self.push_str(&literal);
+                    i = close + 1;
+                }
+                '"' if !quoted => {
+                    let close = skip_quoted(chars, i + 1, '"')?;
+                    self.field_started = true; // "" is an empty field, not no field
+                    self.expand(&chars[i + 1..close], true)?;
+                    i = close + 1;
+                }
+                '$' => match self.parameter(chars, i + 1, quoted)? {
+                    Some(next) => i = next,
+                    None => {
+                        self.push_str("$");
+                        i += 1;
+                    }
+                },
+                '<' if !quoted && chars.get(i + 1) == Some(&'(') => {
+                    let close = skip_parens(chars, i + 2)?;
+                    let command: String = chars[i + 2..close].iter().collect();
+                    let path = self.env.process_output(&command);
+                    self.push_str(&path);
+                    i = close + 1;
+                }
+                ' ' | '\t' | '\n' if self.split_literals && self.split_fields && !quoted => {
+                    self.end_field();
+                    i += 1;
+                }
+                _ if quoted => {
+                    self.push_str(&c.to_string());
+                    i += 1;
+                }
+                _ => {
+                    self.push_unquoted(c);
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Expand a word produced by lex(): remove quotes and backslashes, and
+/// perform tilde, parameter, command, process and pathname expansion. Since
+/// unquoted parameter and command expansions are split on whitespace, and
+/// patterns can match any number of paths, a word can expand to any number of
+/// fields.
+pub fn expand_word(word: &str, env: &dyn ExpandEnv) -> Result<Vec<String>, ParseFailReason> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut expander = Expander::new(env, true);
+    expander.expand(&chars, false)?;
+    expander.end_field();
+
+    let options = env.glob_options();
+    let mut fields = Vec::new();
+    for (field, pattern) in expander.fields {
+        if !has_magic(&pattern) {
+            fields.push(field);
+            continue;
+        }
+        let paths = glob(&pattern);
+        if !paths.is_empty() {
+            fields.extend(paths);
+        } else if options.failglob {
+            return Err(ParseFailReason::NoMatch(field));
+        } else if !options.nullglob {
+            fields.push(field);
+        }
+    }
+    Ok(fields)
+}
+
+/// If `word` (as produced by lex()) is a `NAME=value` assignment,
+/// return the name and the expanded value. The value is expanded like a word,
+/// except that it is never split into fields.
+pub fn expand_assignment(
+    word: &str,
+    env: &dyn ExpandEnv,
+) -> Result<Option<Assignment>, ParseFailReason> {
+    let (name, value) = match word.find('=') {
+        Some(eq) if crate::vars::is_valid_name(&word[..eq]) => (&word[..eq], &word[eq + 1..]),
+        _ => return Ok(None),
+    };
+
+    let chars: Vec<char> = value.chars().collect();
+    let mut expander = Expander::new(env, false);
+    expander.expand(&chars, false)?;
+    Ok(Some((name.to_string(), expander.field)))
+}
+
+/// Expand the words of a simple command into its argv, along with the
+/// `NAME=value` assignments that came before the command name.
+pub fn expand_command(
+    words: &[String],
+    env: &dyn ExpandEnv,
+) -> Result<(Vec<String>, Vec<Assignment>), ParseFailReason> {
+    // Assignments are only recognized before the command name
+    let mut assignments = Vec::new();
+    let mut words = words.iter().peekable();
+    while let Some(word) = words.peek() {
+        match expand_assignment(word, env)? {
+            Some(assignment) => assignments.push(assignment),
+            None => break,
+        }
+        words.next();
+    }
+
+    let mut argv: Vec<String> = Vec::new();
+    for word in words {
+        for word in brace_expand(word) {
+            argv.extend(expand_word(&word, env)?);
+        }
     }
+    Ok((argv, assignments))
 }
 
 // Parse a &[u8] into an i32.
@@ -259,15 +782,55 @@ pub fn parse_numerical(inp: &[u8]) -> Result<i32, ParseFailReason> {
             Some(z) => z,
             None => return Err(ParseFailReason::Invalid(*i as char)),
         };
-        res *= 10;
-        res += val as i32;
+        res = match res.checked_mul(10).and_then(|r| r.checked_add(val as i32)) {
+            Some(r) => r,
+            None => return Err(ParseFailReason::Other("number too large".to_string())),
+        };
     }
     Ok(res)
 }
 
+/// Parse a signal given either as a number (`9`) or as a case-insensitive name
+/// with or without the SIG prefix (`KILL`, `sigkill`).
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(num) = parse_numerical(name.as_bytes()) {
+        return Signal::try_from(num).ok();
+    }
+    let name = name.to_ascii_uppercase();
+    if name.starts_with("SIG") {
+        name.parse().ok()
+    } else {
+        format!("SIG{}", name).parse().ok()
+    }
+}
+
+/// Parse a job specification, i.e. either a `%jobid` or a bare PID.
+pub fn parse_jobspec(arg: &str) -> Result<JobSpec, ParseFailReason> {
+    let arg = arg.as_bytes();
+    let (is_jid, digits) = match arg.first() {
+        Some(b'%') => (true, &arg[1..]),
+        Some(_) => (false, arg),
+        None => return Err(ParseFailReason::EmptyArg),
+    };
+    if digits.is_empty() {
+        return Err(ParseFailReason::EmptyArg);
+    }
+
+    let id = parse_numerical(digits)?;
+    if is_jid {
+        Ok(JobSpec::Jid(id))
+    } else {
+        Ok(JobSpec::Pid(Pid::from_raw(id)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::CommandKind;
+    use crate::jobs::JobState;
+    use std::collections::HashMap;
+    use std::fmt::Write;
 
     // A cursed macro to create a vec of strings from [str] literals
     macro_rules! string_vec {
@@ -279,88 +842,400 @@ mod tests {
         });
     }
 
+    /// A fixed set of variables to expand against
+    struct TestEnv(HashMap<String, String>);
+
+    impl TestEnv {
+        fn new() -> Self {
+            let mut vars = HashMap::new();
+            vars.insert("HOME".to_string(), "/home/msh".to_string());
+            vars.insert("FOO".to_string(), "foo".to_string());
+            vars.insert("SPACED".to_string(), "a  b c".to_string());
+            vars.insert("EMPTY".to_string(), String::new());
+            vars.insert("1".to_string(), "first".to_string());
+            vars.insert("2".to_string(), "second  arg".to_string());
+            vars.insert("@".to_string(), "first second  arg".to_string());
+            TestEnv(vars)
+        }
+    }
+
+    impl ExpandEnv for TestEnv {
+        fn var(&self, name: &str) -> Option<String> {
+            self.0.get(name).cloned()
+        }
+
+        fn last_status(&self) -> i32 {
+            3
+        }
+
+        fn params(&self) -> Vec<String> {
+            string_vec!["first", "second  arg"]
+        }
+
+        // Commands are only ever `echo`, which prints the rest of the command
+        fn command_output(&self, command: &str) -> String {
+            format!("{}\n\n", command.trim_start_matches("echo").trim())
+        }
+
+        fn process_output(&self, command: &str) -> String {
+            format!("/dev/fd/{}", command.len())
+        }
+    }
+
+    /// What a line holding a single simple command parses and expands to
+    #[derive(Debug, PartialEq)]
+    struct ParseResult(JobState, Vec<String>, Vec<(String, String)>);
+
+    fn parseline(line: &str, env: &dyn ExpandEnv) -> Result<ParseResult, ParseFailReason> {
+        let list = crate::ast::parse(line)?;
+        assert_eq!(list.items.len(), 1);
+        let item = &list.items[0];
+        let words = match &item.and_or.first.kind {
+            CommandKind::Simple(words) => words,
+            _ => panic!("Not a simple command: {}", line),
+        };
+        let (argv, assignments) = expand_command(words, env)?;
+        if argv.is_empty() && assignments.is_empty() {
+            return Err(ParseFailReason::EmptyLine);
+        }
+        let jobstate = if item.background {
+            JobState::BG
+        } else {
+            JobState::FG
+        };
+        Ok(ParseResult(jobstate, argv, assignments))
+    }
+
+    fn expand(line: &str) -> Result<Vec<String>, ParseFailReason> {
+        parseline(line, &TestEnv::new()).map(|x| x.1)
+    }
+
     #[test]
     fn test_simple_parse() {
         let input = "run this program";
-        let output = parseline(input).unwrap();
+        let output = parseline(input, &TestEnv::new()).unwrap();
         assert_eq!(
             output,
-            ParseResult(JobState::FG, string_vec!["run", "this", "program"])
+            ParseResult(JobState::FG, string_vec!["run", "this", "program"], vec![])
         )
     }
 
     #[test]
     fn test_ws_parse() {
         let input = "  run        this program           ";
-        let output = parseline(input).unwrap();
+        let output = parseline(input, &TestEnv::new()).unwrap();
         assert_eq!(
             output,
-            ParseResult(JobState::FG, string_vec!["run", "this", "program"])
+            ParseResult(JobState::FG, string_vec!["run", "this", "program"], vec![])
         )
     }
 
     #[test]
     fn test_quoted_parse() {
         let input = "run 'this program'";
-        let output = parseline(input).unwrap();
+        let output = parseline(input, &TestEnv::new()).unwrap();
         assert_eq!(
             output,
-            ParseResult(JobState::FG, string_vec!["run", "this program"])
+            ParseResult(JobState::FG, string_vec!["run", "this program"], vec![])
         )
     }
 
     #[test]
     fn test_fail_parse() {
         let input = "run 'this program";
-        let output = parseline(input);
+        let output = parseline(input, &TestEnv::new());
         assert_eq!(output, Err(ParseFailReason::Unmatched('\'')));
     }
 
     #[test]
     fn test_simple_bg() {
         let input = "run this program &";
-        let output = parseline(input).unwrap();
+        let output = parseline(input, &TestEnv::new()).unwrap();
         assert_eq!(
             output,
-            ParseResult(JobState::BG, string_vec!["run", "this", "program"])
+            ParseResult(JobState::BG, string_vec!["run", "this", "program"], vec![])
         )
     }
 
     #[test]
-    fn test_format_int() {
-        let test_string = b"H %d";
-        let x: i32 = 618206;
-        let ret = format_string_int(test_string, x);
-        let mut correct = [0u8; MSGBUF_LEN];
-        correct[0] = b'H';
-        correct[1] = b' ';
-        correct[2] = '6' as u8;
-        correct[3] = '1' as u8;
-        correct[4] = '8' as u8;
-        correct[5] = '2' as u8;
-        correct[6] = '0' as u8;
-        correct[7] = '6' as u8;
-        println!("{:?}", &ret[0..32]);
-        println!("{:?}", &correct[0..9]);
-        for i in 0..MSGBUF_LEN {
-            assert_eq!(correct[i], ret[i]);
-        }
+    fn test_quote_inside_word() {
+        assert_eq!(expand("run a'b c'd"), Ok(string_vec!["run", "ab cd"]));
+        assert_eq!(expand("run 'a'\"b\"c"), Ok(string_vec!["run", "abc"]));
     }
 
     #[test]
-    fn test_format_ids() {
-        let msgbuf = b"Job [%d] (%d) stopped by %s\n";
-        let jid = 2;
-        let pid = 1058514;
-        let msg = format_string_int(msgbuf, jid);
-        let msg = format_string_int(&msg[..], pid);
-        let msg = format_string_str(&msg[..], "SIGTSTP".as_bytes());
-
-        let correct = "Job [2] (1058514) stopped by SIGTSTP\n".as_bytes();
-        println!("{:?}", &msg[0..32]);
-        println!("{:?}", &correct[0..32]);
-        for i in 0..correct.len() {
-            assert_eq!(msg[i], correct[i]);
-        }
+    fn test_double_quotes() {
+        assert_eq!(
+            expand("echo \"a  b\" \"$FOO bar\" '$FOO'"),
+            Ok(string_vec!["echo", "a  b", "foo bar", "$FOO"])
+        );
+        assert_eq!(expand("echo \"it's\""), Ok(string_vec!["echo", "it's"]));
+        assert_eq!(
+            expand("echo \"a\\\"b\\n\""),
+            Ok(string_vec!["echo", "a\"b\\n"])
+        );
+        assert_eq!(expand("echo \"a"), Err(ParseFailReason::Unmatched('"')));
+    }
+
+    #[test]
+    fn test_empty_quotes() {
+        assert_eq!(expand("echo '' \"\""), Ok(string_vec!["echo", "", ""]));
+    }
+
+    #[test]
+    fn test_backslash() {
+        assert_eq!(
+            expand("echo a\\ b \\'c"),
+            Ok(string_vec!["echo", "a b", "'c"])
+        );
+        assert_eq!(
+            expand("echo \\$FOO \\\\"),
+            Ok(string_vec!["echo", "$FOO", "\\"])
+        );
+        assert_eq!(expand("echo a\\"), Ok(string_vec!["echo", "a\\"]));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            expand("echo $FOO ${FOO}bar x$FOO.y $1"),
+            Ok(string_vec!["echo", "foo", "foobar", "xfoo.y", "first"])
+        );
+        assert_eq!(
+            expand("echo $NOPE $ a$"),
+            Ok(string_vec!["echo", "$", "a$"])
+        );
+        assert_eq!(expand("echo $?"), Ok(string_vec!["echo", "3"]));
+        assert_eq!(
+            expand("echo $$"),
+            Ok(string_vec!["echo", std::process::id().to_string()])
+        );
+    }
+
+    #[test]
+    fn test_positional_params() {
+        assert_eq!(
+            expand("echo $2 \"$2\" ${1}x"),
+            Ok(string_vec![
+                "echo",
+                "second",
+                "arg",
+                "second  arg",
+                "firstx"
+            ])
+        );
+        assert_eq!(
+            expand("echo $@"),
+            Ok(string_vec!["echo", "first", "second", "arg"])
+        );
+        assert_eq!(
+            expand("echo \"<$@>\""),
+            Ok(string_vec!["echo", "<first", "second  arg>"])
+        );
+    }
+
+    #[test]
+    fn test_field_splitting() {
+        assert_eq!(
+            expand("echo $SPACED"),
+            Ok(string_vec!["echo", "a", "b", "c"])
+        );
+        assert_eq!(
+            expand("echo x$SPACED"),
+            Ok(string_vec!["echo", "xa", "b", "c"])
+        );
+        assert_eq!(
+            expand("echo \"$SPACED\""),
+            Ok(string_vec!["echo", "a  b c"])
+        );
+        assert_eq!(
+            expand("echo $EMPTY \"$EMPTY\""),
+            Ok(string_vec!["echo", ""])
+        );
+        assert_eq!(expand("$EMPTY"), Err(ParseFailReason::EmptyLine));
+    }
+
+    #[test]
+    fn test_default_values() {
+        assert_eq!(
+            expand("echo ${NOPE:-a b}"),
+            Ok(string_vec!["echo", "a", "b"])
+        );
+        assert_eq!(
+            expand("echo \"${NOPE:-a  b}\""),
+            Ok(string_vec!["echo", "a  b"])
+        );
+        assert_eq!(
+            expand("echo ${EMPTY:-$FOO}"),
+            Ok(string_vec!["echo", "foo"])
+        );
+        assert_eq!(expand("echo ${EMPTY-$FOO}x"), Ok(string_vec!["echo", "x"]));
+        assert_eq!(expand("echo ${FOO:-'} {'}"), Ok(string_vec!["echo", "foo"]));
+        assert_eq!(
+            expand("echo ${NOPE:-'} {'}"),
+            Ok(string_vec!["echo", "} {"])
+        );
+        assert_eq!(expand("echo ${FOO"), Err(ParseFailReason::Unmatched('{')));
+        assert_eq!(expand("echo ${FOO+x}"), Err(ParseFailReason::Invalid('+')));
+        assert_eq!(expand("echo ${}"), Err(ParseFailReason::Invalid('}')));
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        assert_eq!(
+            expand("echo $(echo a  b) x$(echo)y"),
+            Ok(string_vec!["echo", "a", "b", "xy"])
+        );
+        assert_eq!(
+            expand("echo \"$(echo a  b)\" '$(echo a)'"),
+            Ok(string_vec!["echo", "a  b", "$(echo a)"])
+        );
+        assert_eq!(
+            expand("echo $(echo (a) ')' \")\") \"$(echo \")\")\""),
+            Ok(string_vec!["echo", "(a)", "')'", "\")\"", "\")\""])
+        );
+        // Assignments aren't split
+        let output = parseline("X=$(echo a  b) run", &TestEnv::new()).unwrap();
+        assert_eq!(
+            output,
+            ParseResult(
+                JobState::FG,
+                string_vec!["run"],
+                vec![("X".to_string(), "a  b".to_string())]
+            )
+        );
+        assert_eq!(
+            expand("echo $(echo a"),
+            Err(ParseFailReason::Unmatched('('))
+        );
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        assert_eq!(
+            expand("diff <(sort a) <(sort  bb) '<(a)'"),
+            Ok(string_vec!["diff", "/dev/fd/6", "/dev/fd/8", "<(a)"])
+        );
+        assert_eq!(expand("cat <(a; b)"), Ok(string_vec!["cat", "/dev/fd/4"]));
+        assert_eq!(expand("cat <(a"), Err(ParseFailReason::Unmatched('(')));
+    }
+
+    #[test]
+    fn test_tilde() {
+        assert_eq!(
+            expand("cd ~ ~/src a~ '~'"),
+            Ok(string_vec!["cd", "/home/msh", "/home/msh/src", "a~", "~"])
+        );
+        // ~user comes from the password database, not from $HOME
+        let me = nix::unistd::User::from_uid(nix::unistd::getuid())
+            .unwrap()
+            .unwrap();
+        let home = me.dir.to_str().unwrap();
+        assert_eq!(
+            expand(&format!("cd ~{} ~{}/src", me.name, me.name)),
+            Ok(string_vec!["cd", home, format!("{}/src", home)])
+        );
+        assert_eq!(
+            expand("cd ~nosuchuser/x"),
+            Ok(string_vec!["cd", "~nosuchuser/x"])
+        );
+    }
+
+    #[test]
+    fn test_ampersand_and_comments() {
+        let output = parseline("run a&", &TestEnv::new()).unwrap();
+        assert_eq!(
+            output,
+            ParseResult(JobState::BG, string_vec!["run", "a"], vec![])
+        );
+        let output = parseline("run '&'", &TestEnv::new()).unwrap();
+        assert_eq!(
+            output,
+            ParseResult(JobState::FG, string_vec!["run", "&"], vec![])
+        );
+        assert_eq!(expand("run a#b # comment"), Ok(string_vec!["run", "a#b"]));
+        assert_eq!(expand("# just a comment"), Err(ParseFailReason::EmptyLine));
+        // A comment ends with its line
+        let tokens = lex("a # b; c\nd").unwrap();
+        let texts: Vec<_> = tokens.iter().map(|token| &token.text[..]).collect();
+        assert_eq!(texts, vec!["a", "\n", "d"]);
+    }
+
+    #[test]
+    fn test_assignments() {
+        let output = parseline("A=1 B=\"$FOO  bar\" C=$SPACED D=~ run E=2", &TestEnv::new());
+        assert_eq!(
+            output,
+            Ok(ParseResult(
+                JobState::FG,
+                string_vec!["run", "E=2"],
+                vec![
+                    ("A".to_string(), "1".to_string()),
+                    ("B".to_string(), "foo  bar".to_string()),
+                    ("C".to_string(), "a  b c".to_string()),
+                    ("D".to_string(), "/home/msh".to_string()),
+                ]
+            ))
+        );
+        let output = parseline("A= ", &TestEnv::new());
+        assert_eq!(
+            output,
+            Ok(ParseResult(
+                JobState::FG,
+                vec![],
+                vec![("A".to_string(), String::new())]
+            ))
+        );
+        assert_eq!(expand("'A'=1 1A=2"), Ok(string_vec!["A=1", "1A=2"]));
+    }
+
+    #[test]
+    fn test_parse_jobspec() {
+        assert_eq!(parse_jobspec("%3"), Ok(JobSpec::Jid(3)));
+        assert_eq!(parse_jobspec("1234"), Ok(JobSpec::Pid(Pid::from_raw(1234))));
+        assert_eq!(parse_jobspec("%"), Err(ParseFailReason::EmptyArg));
+        assert_eq!(parse_jobspec("a"), Err(ParseFailReason::Invalid('a')));
+        assert!(parse_jobspec("99999999999").is_err());
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("9"), Some(Signal::SIGKILL));
+        assert_eq!(parse_signal("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(parse_signal("sigint"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("BOGUS"), None);
+    }
+
+    #[test]
+    fn test_msgbuf() {
+        let mut msg = MsgBuf::new();
+        assert_eq!(msg.as_str(), "");
+        let (jid, pid, signal) = (2, 1058514, "SIGTSTP");
+        writeln!(msg, "Job [{}] ({}) stopped by {}", jid, pid, signal).unwrap();
+        assert_eq!(msg.as_str(), "Job [2] (1058514) stopped by SIGTSTP\n");
+        assert_eq!(msg.as_bytes(), msg.as_str().as_bytes());
+    }
+
+    #[test]
+    fn test_msgbuf_numbers() {
+        let mut msg = MsgBuf::new();
+        write!(msg, "{} {} {:05} {:05} {}", -42, 0, 7, -7, i64::MIN).unwrap();
+        assert_eq!(msg.as_str(), "-42 0 00007 -0007 -9223372036854775808");
+    }
+
+    #[test]
+    fn test_msgbuf_truncation() {
+        let mut msg = MsgBuf::new();
+        let long = "x".repeat(MSGBUF_LEN - 1);
+        write!(msg, "{}", long).unwrap();
+        // Only one byte is left, which a two-byte character doesn't fit in
+        assert!(write!(msg, "é").is_err());
+        assert_eq!(msg.as_str(), long);
+        assert!(write!(msg, "{}", 12).is_err());
+        assert_eq!(msg.as_bytes().len(), MSGBUF_LEN);
+        assert!(msg.as_str().ends_with("x1"));
+        assert!(write!(msg, "more").is_err());
+        assert_eq!(msg.as_bytes().len(), MSGBUF_LEN);
     }
 }
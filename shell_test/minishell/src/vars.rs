@@ -0,0 +1,335 @@
+/// The shell's variable table. Every variable the shell knows about lives here,
+/// whether it came from the environment rustymsh was started with, an `export`,
+/// or a plain `NAME=value` assignment. Only exported variables are passed on to
+/// the programs the shell runs.
+/* Notes:
+  Like the terminal state, the variable table is only touched by the main loop,
+  never by the signal handlers, so there's nothing to race against.
+*/
+use crate::glob::GlobOptions;
+use crate::substitution;
+use crate::util::ExpandEnv;
+use std::collections::BTreeMap;
+
+/// A single shell variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Var {
+    value: String,
+    exported: bool,
+}
+
+impl Var {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+    pub fn exported(&self) -> bool {
+        self.exported
+    }
+}
+
+/// The variables of the shell, kept sorted by name so that `set`, `export` and
+/// `env` list them in a stable order, along with the positional parameters.
+#[derive(Default)]
+pub struct VarTable {
+    vars: BTreeMap<String, Var>,
+    arg0: String,        // $0: the name of the shell or script
+    params: Vec<String>, // $1, $2, ...
+    last_status: i32,    // $?
+    glob_options: GlobOptions,
+}
+
+impl VarTable {
+    pub fn new() -> Self {
+        VarTable {
+            vars: BTreeMap::new(),
+            arg0: String::new(),
+            params: Vec::new(),
+            last_status: 0,
+            glob_options: GlobOptions::default(),
+        }
+    }
+
+    /// Build a table holding the shell's own environment, all of it exported
+    pub fn from_env() -> Self {
+        let mut table = VarTable::new();
+        for (name, value) in std::env::vars() {
+            table.export(&name, Some(value));
+        }
+        table
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value())
+    }
+
+    /// Set a variable, keeping it exported if it already was
+    pub fn set(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                self.vars.insert(
+                    name.to_string(),
+                    Var {
+                        value,
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Mark a variable as exported, setting its value first if one is given.
+    /// Exporting a variable that doesn't exist creates it, empty.
+    pub fn export(&mut self, name: &str, value: Option<String>) {
+        let var = self.vars.entry(name.to_string()).or_insert(Var {
+            value: String::new(),
+            exported: true,
+        });
+        var.exported = true;
+        if let Some(value) = value {
+            var.value = value;
+        }
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// Apply a command's own `NAME=value` assignments while it runs as a
+    /// builtin, returning the previous state of those variables for restore()
+    pub fn assign_temporarily(
+        &mut self,
+        assignments: &[(String, String)],
+    ) -> Vec<(String, Option<Var>)> {
+        let mut saved = Vec::new();
+        for (name, value) in assignments {
+            saved.push((name.clone(), self.vars.get(name).cloned()));
+            self.export(name, Some(value.clone()));
+        }
+        saved
+    }
+
+    /// Undo assign_temporarily()
+    pub fn restore(&mut self, saved: Vec<(String, Option<Var>)>) {
+        // Go backwards, in case the same name was assigned twice
+        for (name, var) in saved.into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Record the status of the command that just finished, for `$?`
+    pub fn set_last_status(&mut self, status: i32) {
+        self.last_status = status;
+    }
+
+    pub fn set_arg0(&mut self, arg0: String) {
+        self.arg0 = arg0;
+    }
+
+    /// Replace the positional parameters, returning the old ones
+    pub fn set_params(&mut self, params: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.params, params)
+    }
+
+    /// Drop the first `n` positional parameters. Returns false, leaving them
+    /// alone, if there aren't that many.
+    pub fn shift(&mut self, n: usize) -> bool {
+        if n > self.params.len() {
+            return false;
+        }
+        self.params.drain(..n);
+        true
+    }
+
+    /// The options set with `set -o nullglob` and `set -o failglob`
+    pub fn glob_options_mut(&mut self) -> &mut GlobOptions {
+        &mut self.glob_options
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Var)> {
+        self.vars.iter()
+    }
+
+    /// The environment to give a child process: every exported variable, with
+    /// the command's own `NAME=value` assignments layered on top.
+    pub fn environ(&self, assignments: &[(String, String)]) -> Vec<(String, String)> {
+        let mut env: BTreeMap<&str, &str> = self
+            .vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (&name[..], &var.value[..]))
+            .collect();
+        for (name, value) in assignments {
+            env.insert(name, value);
+        }
+        env.into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+}
+
+impl ExpandEnv for VarTable {
+    fn var(&self, name: &str) -> Option<String> {
+        match name {
+            "0" => Some(self.arg0.clone()),
+            "#" => Some(self.params.len().to_string()),
+            "@" | "*" => Some(self.params.join(" ")),
+            _ => match name.parse::<usize>() {
+                Ok(n) => n.checked_sub(1).and_then(|n| self.params.get(n)).cloned(),
+                Err(_) => self.get(name).map(|x| x.to_string()),
+            },
+        }
+    }
+
+    fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    fn params(&self) -> Vec<String> {
+        self.params.clone()
+    }
+
+    fn command_output(&self, command: &str) -> String {
+        substitution::command_output(command)
+    }
+
+    fn process_output(&self, command: &str) -> String {
+        substitution::process_output(command)
+    }
+
+    fn glob_options(&self) -> GlobOptions {
+        self.glob_options
+    }
+}
+
+/// Whether `name` can be used as a variable name: letters, digits and
+/// underscores, not starting with a digit.
+pub fn is_valid_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Quote a value so that the shell would read it back as the same word
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+static mut SHELL_VARS: Option<VarTable> = None;
+
+/// Load the shell's environment into the variable table
+pub fn init() {
+    unsafe {
+        SHELL_VARS = Some(VarTable::from_env());
+    }
+}
+
+/// Get a handle to the shell's variable table
+pub fn vars() -> &'static mut VarTable {
+    unsafe { (*std::ptr::addr_of_mut!(SHELL_VARS)).as_mut().unwrap() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_export() {
+        let mut vars = VarTable::new();
+        vars.set("A", "1".to_string());
+        vars.export("B", Some("2".to_string()));
+        vars.export("C", None);
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("C"), Some(""));
+        assert_eq!(
+            vars.environ(&[]),
+            vec![
+                ("B".to_string(), "2".to_string()),
+                ("C".to_string(), String::new())
+            ]
+        );
+
+        // Setting an exported variable keeps it exported
+        vars.set("B", "3".to_string());
+        vars.export("A", None);
+        assert_eq!(
+            vars.environ(&[]),
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "3".to_string()),
+                ("C".to_string(), String::new())
+            ]
+        );
+
+        vars.unset("A");
+        vars.unset("C");
+        assert_eq!(vars.get("A"), None);
+        assert_eq!(vars.environ(&[]), vec![("B".to_string(), "3".to_string())]);
+    }
+
+    #[test]
+    fn test_environ_assignments() {
+        let mut vars = VarTable::new();
+        vars.export("A", Some("1".to_string()));
+        vars.set("B", "2".to_string());
+        let assignments = vec![
+            ("A".to_string(), "x".to_string()),
+            ("B".to_string(), "y".to_string()),
+        ];
+        assert_eq!(vars.environ(&assignments), assignments);
+        // The assignments only apply to that one environment
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.environ(&[]), vec![("A".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn test_assign_temporarily() {
+        let mut vars = VarTable::new();
+        vars.set("A", "1".to_string());
+        let saved = vars.assign_temporarily(&[
+            ("A".to_string(), "x".to_string()),
+            ("B".to_string(), "y".to_string()),
+            ("A".to_string(), "z".to_string()),
+        ]);
+        assert_eq!(vars.get("A"), Some("z"));
+        assert_eq!(vars.environ(&[]).len(), 2);
+
+        vars.restore(saved);
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("B"), None);
+        assert!(vars.environ(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_positional_params() {
+        let mut vars = VarTable::new();
+        vars.set_arg0("script.sh".to_string());
+        vars.set_params(vec!["a".to_string(), "b c".to_string()]);
+        assert_eq!(vars.var("0"), Some("script.sh".to_string()));
+        assert_eq!(vars.var("2"), Some("b c".to_string()));
+        assert_eq!(vars.var("3"), None);
+        assert_eq!(vars.var("#"), Some("2".to_string()));
+        assert_eq!(vars.var("@"), Some("a b c".to_string()));
+        vars.set_last_status(127);
+        assert_eq!(vars.last_status(), 127);
+
+        assert!(!vars.shift(3));
+        assert!(vars.shift(1));
+        assert_eq!(vars.params(), vec!["b c".to_string()]);
+    }
+
+    #[test]
+    fn test_names_and_quoting() {
+        assert!(is_valid_name("_FOO1"));
+        assert!(!is_valid_name("1FOO"));
+        assert!(!is_valid_name("FOO-BAR"));
+        assert!(!is_valid_name(""));
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+}
@@ -0,0 +1,50 @@
+//! mycont - Another handy routine for testing your tiny shell
+//!
+//! usage: mycont <n>
+//! Sleeps for <n> seconds and sends SIGTSTP to its process group, like mystop.
+//! A helper process outside of that group sends SIGCONT to the group <n>
+//! seconds later, after which mycont sleeps for <n> more seconds and exits.
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, getpid, setpgid, ForkResult, Pid};
+use std::thread::sleep;
+use std::time::Duration;
+
+fn spin(secs: u32) {
+    for _ in 0..secs {
+        sleep(Duration::from_secs(1));
+    }
+}
+
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    if argv.len() != 2 {
+        eprintln!("Usage: {} <n>", argv[0]);
+        std::process::exit(0);
+    }
+    let secs: u32 = argv[1].parse().unwrap_or(0);
+    let group = Pid::from_raw(-getpid().as_raw());
+
+    let helper = match fork() {
+        Ok(ForkResult::Child) => {
+            // Leave the process group so that the SIGTSTP doesn't stop us too
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+            spin(2 * secs);
+            if kill(group, Signal::SIGCONT).is_err() {
+                eprint!("kill (cont) error");
+            }
+            std::process::exit(0);
+        }
+        Ok(ForkResult::Parent { child }) => child,
+        Err(_) => std::process::exit(1),
+    };
+
+    spin(secs);
+    if kill(group, Signal::SIGTSTP).is_err() {
+        eprint!("kill (tstp) error");
+    }
+
+    // We only get here once the helper has continued us
+    let _ = waitpid(helper, None);
+    spin(secs);
+}
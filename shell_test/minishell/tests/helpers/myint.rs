@@ -0,0 +1,25 @@
+//! myint - Another handy routine for testing your tiny shell
+//!
+//! usage: myint <n>
+//! Sleeps for <n> seconds and sends SIGINT to itself.
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::getpid;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    if argv.len() != 2 {
+        eprintln!("Usage: {} <n>", argv[0]);
+        std::process::exit(0);
+    }
+    let secs: u32 = argv[1].parse().unwrap_or(0);
+
+    for _ in 0..secs {
+        sleep(Duration::from_secs(1));
+    }
+
+    if kill(getpid(), Signal::SIGINT).is_err() {
+        eprint!("kill (int) error");
+    }
+}
@@ -0,0 +1,19 @@
+//! myspin - A handy program for testing your tiny shell
+//!
+//! usage: myspin <n>
+//! Sleeps for <n> seconds in 1-second chunks.
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    if argv.len() != 2 {
+        eprintln!("Usage: {} <n>", argv[0]);
+        std::process::exit(0);
+    }
+    let secs: u32 = argv[1].parse().unwrap_or(0);
+
+    for _ in 0..secs {
+        sleep(Duration::from_secs(1));
+    }
+}
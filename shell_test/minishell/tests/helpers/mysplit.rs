@@ -0,0 +1,27 @@
+//! mysplit - Another handy routine for testing your tiny shell
+//!
+//! usage: mysplit <n>
+//! Fork a child that spins for <n> seconds in 1-second chunks.
+use nix::sys::wait::wait;
+use nix::unistd::{fork, ForkResult};
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    let argv: Vec<String> = std::env::args().collect();
+    if argv.len() != 2 {
+        eprintln!("Usage: {} <n>", argv[0]);
+        std::process::exit(0);
+    }
+    let secs: u32 = argv[1].parse().unwrap_or(0);
+
+    if let Ok(ForkResult::Child) = fork() {
+        for _ in 0..secs {
+            sleep(Duration::from_secs(1));
+        }
+        std::process::exit(0);
+    }
+
+    // Parent waits for child to terminate
+    let _ = wait();
+}
@@ -0,0 +1,263 @@
+//! Runs rustymsh on a pseudo-terminal, the way a user would, to check that the
+//! shell hands the terminal to foreground jobs and takes it back afterwards,
+//! and that the line editor works.
+use nix::pty::openpty;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A shell running on the slave side of a pty, with everything it writes to
+/// the terminal collected in `output`.
+struct PtyShell {
+    child: Child,
+    master: File,
+    output: Arc<Mutex<String>>,
+}
+
+impl PtyShell {
+    /// Start a shell that doesn't keep its history in a file
+    fn spawn() -> Self {
+        Self::spawn_with_history(Path::new(""))
+    }
+
+    fn spawn_with_history(histfile: &Path) -> Self {
+        let pty = openpty(None, None).expect("Could not open a pty");
+        let slave = |fd| unsafe { Stdio::from_raw_fd(nix::unistd::dup(fd).unwrap()) };
+
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_rustymsh"));
+        cmd.env("HISTFILE", histfile)
+            .env_remove("PS1")
+            .stdin(slave(pty.slave))
+            .stdout(slave(pty.slave))
+            .stderr(slave(pty.slave));
+        unsafe {
+            // Start a new session with the pty as its controlling terminal
+            cmd.pre_exec(|| {
+                nix::unistd::setsid().map_err(|_| std::io::Error::last_os_error())?;
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn().expect("Could not start rustymsh");
+        nix::unistd::close(pty.slave).unwrap();
+
+        let master = unsafe { File::from_raw_fd(pty.master) };
+        let output = Arc::new(Mutex::new(String::new()));
+        let mut reader = master.try_clone().unwrap();
+        let sink = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            // Reads fail with EIO once the shell closes the terminal
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+        });
+
+        let shell = PtyShell {
+            child,
+            master,
+            output,
+        };
+        shell.expect("msh> ");
+        shell
+    }
+
+    /// Type some input into the terminal
+    fn send(&mut self, input: &str) {
+        self.master.write_all(input.as_bytes()).unwrap();
+        // Give the shell a moment to react, so that later input isn't read by
+        // the wrong process
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    /// Wait for `needle` to show up on the terminal, panicking after a timeout
+    fn expect(&self, needle: &str) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if self.output.lock().unwrap().contains(needle) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!(
+            "Timed out waiting for {:?}. Terminal output:\n{}",
+            needle,
+            self.output.lock().unwrap()
+        );
+    }
+}
+
+impl Drop for PtyShell {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_fg_job_reads_terminal() {
+    let mut shell = PtyShell::spawn();
+    shell.send("/bin/sh -c 'read x; echo got:$x'\n");
+    shell.send("hello\n");
+    shell.expect("got:hello");
+
+    // The shell must have the terminal back to read the next command
+    shell.send("/bin/echo back at the prompt\n");
+    shell.expect("back at the prompt");
+    shell.send("quit\n");
+}
+
+#[test]
+fn test_terminal_signals_go_to_fg_job() {
+    let mut shell = PtyShell::spawn();
+    shell.send("/bin/sleep 10\n");
+    shell.send("\x03"); // Ctrl-C
+    shell.expect("terminated by signal 2");
+
+    shell.send("/bin/echo still alive\n");
+    shell.expect("still alive");
+    shell.send("quit\n");
+}
+
+#[test]
+fn test_stopped_job_gets_terminal_on_fg() {
+    let mut shell = PtyShell::spawn();
+    shell.send("/bin/sh -c 'read x; echo got:$x'\n");
+    shell.send("\x1a"); // Ctrl-Z
+    shell.expect("stopped by signal 20");
+
+    shell.send("jobs\n");
+    shell.expect("Stopped /bin/sh");
+    shell.send("fg %1\n");
+    shell.send("again\n");
+    shell.expect("got:again");
+    shell.send("quit\n");
+}
+
+#[test]
+fn test_subshell_is_one_job() {
+    let mut shell = PtyShell::spawn();
+    // The quotes keep the terminal's echo of the input from matching the output
+    shell.send("(/bin/sh -c 'read x; echo got:$x'; /bin/echo sec''ond) || /bin/echo st''opped\n");
+    shell.send("\x1a"); // Ctrl-Z stops the whole subshell
+    shell.expect("stopped by signal 20");
+    // Like bash, the rest of the line goes on as if the subshell had failed
+    shell.expect("\nstopped");
+
+    shell.send("jobs\n");
+    shell.expect("Stopped (/bin/sh");
+    shell.send("fg %1\n");
+    shell.send("again\n");
+    shell.expect("got:again");
+    shell.expect("\nsecond");
+
+    shell.send("(/bin/sleep 10; /bin/echo not reached) || /bin/echo inter''rupted\n");
+    shell.send("\x03"); // Ctrl-C
+    shell.expect("\ninterrupted");
+    shell.send("quit\n");
+}
+
+#[test]
+fn test_line_editing_and_history() {
+    let histfile = std::env::temp_dir().join(format!("rustymsh_history.{}", std::process::id()));
+    let _ = std::fs::remove_file(&histfile);
+    let mut shell = PtyShell::spawn_with_history(&histfile);
+
+    // Left arrow, then insert in the middle of the line
+    shell.send("/bin/echo ab\x1b[Dx\r");
+    shell.expect("\naxb");
+    // Up arrow brings the line back to be edited
+    shell.send("\x1b[A\x1b[D\x7fy\r");
+    shell.expect("\nayb");
+    // History expansion shows the line that it ran
+    shell.send("!1 z\r");
+    shell.expect("/bin/echo axb z");
+    shell.expect("\naxb z");
+    // Tab completes builtin names
+    shell.send("histo\t\r");
+    shell.expect("    3  /bin/echo axb z");
+    shell.send("quit\n");
+
+    let saved = std::fs::read_to_string(&histfile).unwrap();
+    let _ = std::fs::remove_file(&histfile);
+    assert_eq!(
+        saved,
+        "/bin/echo axb\n/bin/echo ayb\n/bin/echo axb z\nhistory \nquit\n"
+    );
+}
+
+#[test]
+fn test_done_jobs_reported_at_prompt() {
+    let mut shell = PtyShell::spawn();
+    shell.send("/bin/sh -c 'sleep 0.2; exit 3' &\n");
+    thread::sleep(Duration::from_millis(500));
+    // Nothing is said until the next prompt
+    assert!(!shell.output.lock().unwrap().contains("Exit"));
+    shell.send("\n");
+    shell.expect("\r\n[1]+ Exit 3  /bin/sh -c 'sleep 0.2; exit 3' &\r\nmsh> ");
+
+    shell.send("/bin/sleep 0.2 &\n");
+    thread::sleep(Duration::from_millis(500));
+    shell.send("\n");
+    shell.expect("\r\n[1]+ Done  /bin/sleep 0.2 &\r\nmsh> ");
+    shell.send("quit\n");
+}
+
+#[test]
+fn test_prompt_shows_status() {
+    let mut shell = PtyShell::spawn();
+    shell.send("PS1='[\\?] \\j> '\n");
+    shell.send("/bin/false\n");
+    shell.expect("[1] 0> ");
+    shell.send("/bin/sleep 10 &\n");
+    shell.expect("[0] 1> ");
+    shell.send("exit\n");
+}
+
+#[test]
+fn test_traps() {
+    let mut shell = PtyShell::spawn();
+    shell.send("trap '/bin/echo trap''ped' INT\n");
+    shell.send("/bin/sleep 10\n");
+    shell.send("\x03"); // Ctrl-C goes to the job, and the trap runs after it
+    shell.expect("terminated by signal 2");
+    shell.expect("\ntrapped");
+
+    shell.send("trap '/bin/echo by''e' EXIT\n");
+    shell.send("exit\n");
+    shell.expect("\nbye");
+}
+
+#[test]
+fn test_substitutions() {
+    let mut shell = PtyShell::spawn();
+    // A substitution's command runs with the terminal, like the shell itself
+    shell.send("/bin/echo got:$(/bin/sh -c 'read x; echo $x')\n");
+    shell.send("hello\n");
+    shell.expect("got:hello");
+
+    shell.send("/usr/bin/diff <(/bin/echo one) <(/bin/echo two)\n");
+    shell.expect("< one");
+    shell.expect("> two");
+
+    // Ctrl-C interrupts the helper, which isn't a job of its own
+    shell.send("/bin/echo x$(/bin/sleep 10)y\n");
+    shell.send("\x03");
+    shell.expect("xy");
+    shell.send("/bin/echo still ali''ve\n");
+    shell.expect("\nstill alive");
+    shell.send("quit\n");
+}
@@ -0,0 +1,258 @@
+//! A Rust port of sdriver.pl. Each test feeds one of the trace files in the
+//! parent directory (or our copy of it in tests/traces) to `rustymsh -p`,
+//! sending the scripted signals along the way, and compares what the shell
+//! prints against a golden file in tests/expected. The golden files for trace01
+//! to trace16 are the output of the mshref reference shell (see mshref.out),
+//! with the run-specific parts normalized away. The traces from trace17 on test
+//! rustymsh's own behavior: mshref leaves a job that something else continues
+//! marked as stopped, and doesn't have kill, wait or disown. Their golden files
+//! were written by hand.
+/* Notes:
+  `/bin/ps a` only lists processes that have a controlling terminal, and the
+  trace output depends on seeing the helper programs in that list. So each
+  shell is started in its own session with a fresh pty as its controlling
+  terminal, while stdin and stdout are still pipes, as with sdriver.pl. This
+  also gives us a way to tell this trace's processes apart from those of traces
+  running in parallel: they're the ones on our pty.
+*/
+use nix::pty::openpty;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// The helper programs that the traces run, as `./name`
+const HELPERS: &[&str] = &["myspin", "mysplit", "mystop", "myint", "mycont"];
+
+/// Where cargo put the helper programs. They're examples, which `cargo test`
+/// builds next to the deps directory that this test runs from.
+fn helpers_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let dir = exe.parent().unwrap().parent().unwrap().join("examples");
+    if HELPERS.iter().any(|name| !dir.join(name).exists()) {
+        panic!("The helper programs are missing: run `cargo build --examples` first");
+    }
+    dir
+}
+
+/// Create a directory for the shell to run in, with the helper programs in it
+fn setup_workdir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("traces");
+    fs::create_dir_all(&dir).unwrap();
+    let helpers = helpers_dir();
+    for name in HELPERS {
+        // Tests run in parallel, so someone else may have beaten us to it
+        let _ = std::os::unix::fs::symlink(helpers.join(name), dir.join(name));
+    }
+    dir
+}
+
+/// Kill everything left over in the session led by `sid`, e.g. background
+/// jobs that were still running when the shell exited.
+fn kill_session(sid: Pid) {
+    for entry in fs::read_dir("/proc").unwrap().flatten() {
+        let pid = match entry.file_name().to_str().and_then(|x| x.parse().ok()) {
+            Some(pid) => Pid::from_raw(pid),
+            None => continue,
+        };
+        if nix::unistd::getsid(Some(pid)) == Ok(sid) {
+            let _ = kill(pid, Signal::SIGKILL);
+        }
+    }
+}
+
+/// Where the trace file is. The shared ones in the parent directory are also
+/// run by sdriver.pl against the C shells, which take backslashes literally.
+/// Where a trace needs quoting to mean the same thing to rustymsh, e.g.
+/// `'\046'` for `\046`, our own copy in tests/traces is used instead.
+fn trace_path(trace: &str) -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let own = manifest_dir
+        .join("tests")
+        .join("traces")
+        .join(format!("{}.txt", trace));
+    if own.exists() {
+        own
+    } else {
+        manifest_dir.join("..").join(format!("{}.txt", trace))
+    }
+}
+
+/// Run the trace through the shell, returning everything that the driver
+/// would have printed: the trace's comments, followed by the shell output.
+fn run_trace(trace: &str) -> (String, String) {
+    let tracefile = fs::read_to_string(trace_path(trace)).unwrap();
+
+    let pty = openpty(None, None).expect("Could not open a pty");
+    let tty = fs::read_link(format!("/proc/self/fd/{}", pty.slave)).unwrap();
+    let tty = tty
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/dev/")
+        .to_string();
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_rustymsh"));
+    cmd.arg("-p")
+        .current_dir(setup_workdir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let (master, slave) = (pty.master, pty.slave);
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        cmd.pre_exec(move || {
+            nix::unistd::setsid().map_err(|_| std::io::Error::last_os_error())?;
+            if libc::ioctl(slave, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            libc::close(slave);
+            libc::close(master);
+            Ok(())
+        });
+    }
+    let mut shell = cmd.spawn().expect("Could not start rustymsh");
+    nix::unistd::close(slave).unwrap();
+    let shell_pid = Pid::from_raw(shell.id() as i32);
+
+    // Drain the shell's output as we go, so that it never blocks on a full pipe
+    let mut stdout = shell.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).unwrap();
+        output
+    });
+
+    let mut comments = String::new();
+    let mut writer = shell.stdin.take();
+    for line in tracefile.lines() {
+        if line.starts_with('#') {
+            comments.push_str(line);
+            comments.push('\n');
+        } else if line.trim().is_empty() {
+            continue;
+        } else if line.contains("TSTP") {
+            kill(shell_pid, Signal::SIGTSTP).unwrap();
+        } else if line.contains("INT") {
+            kill(shell_pid, Signal::SIGINT).unwrap();
+        } else if line.contains("QUIT") {
+            kill(shell_pid, Signal::SIGQUIT).unwrap();
+        } else if line.contains("KILL") {
+            kill(shell_pid, Signal::SIGKILL).unwrap();
+        } else if line.contains("CLOSE") {
+            writer = None;
+        } else if line.contains("WAIT") {
+            shell.wait().unwrap();
+        } else if let Some(secs) = line.strip_prefix("SLEEP ") {
+            thread::sleep(Duration::from_secs(secs.trim().parse().unwrap()));
+        } else if let Some(w) = writer.as_mut() {
+            // The shell may already be gone, e.g. after quit
+            let _ = writeln!(w, "{}", line);
+        }
+    }
+
+    drop(writer);
+    let output = reader.join().unwrap();
+    shell.wait().unwrap();
+    kill_session(shell_pid);
+    nix::unistd::close(master).unwrap();
+
+    (format!("{}{}", comments, output), tty)
+}
+
+/// Replace the run-specific parts of the output: PIDs become `(PID)`, and the
+/// output of `ps a` is cut down to the helper programs run on our terminal.
+/// Trailing whitespace is also dropped, since mshref keeps the trailing spaces
+/// of command lines in some places and not in others.
+fn normalize(output: &str, tty: &str) -> String {
+    let mut normalized = String::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let line = if fields == ["PID", "TTY", "STAT", "TIME", "COMMAND"] {
+            "STAT COMMAND".to_string()
+        } else if fields.len() >= 5 && fields[0].parse::<i32>().is_ok() {
+            let command = fields[4..].join(" ");
+            if fields[1] != tty || !command.starts_with("./") {
+                continue;
+            }
+            format!("{} {}", &fields[2][..1], command)
+        } else {
+            normalize_pids(line)
+        };
+        normalized.push_str(line.trim_end());
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Replace every `(1234)` with `(PID)`
+fn normalize_pids(line: &str) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('(') {
+        result.push_str(&rest[..=start]);
+        rest = &rest[start + 1..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits > 0 && rest[digits..].starts_with(')') {
+            result.push_str("PID");
+            rest = &rest[digits..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn check_trace(trace: &str) {
+    let (output, tty) = run_trace(trace);
+    let expected_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("expected")
+        .join(format!("{}.out", trace));
+    let expected = fs::read_to_string(expected_path).unwrap();
+    assert_eq!(
+        normalize(&output, &tty),
+        expected,
+        "Unexpected output for {}. Raw output:\n{}",
+        trace,
+        output
+    );
+}
+
+macro_rules! trace_tests {
+    ($($name:ident),*) => {
+        $(
+            #[test]
+            fn $name() {
+                check_trace(stringify!($name));
+            }
+        )*
+    };
+}
+
+trace_tests!(
+    trace01, trace02, trace03, trace04, trace05, trace06, trace07, trace08, trace09, trace10,
+    trace11, trace12, trace13, trace14, trace15, trace16, trace17, trace18, trace19
+);
+
+#[test]
+fn test_normalize() {
+    let output = "Job [1] (26916) stopped by signal 20\n\
+                  [1] (26916) Stopped ./mysplit 4 \n\
+                  \x20 PID TTY      STAT   TIME COMMAND\n\
+                  \x20 941 tty7     Ss+    0:05 /usr/bin/X :0\n\
+                  26913 pts/2    S+     0:00 /usr/bin/perl ./sdriver.pl\n\
+                  26916 pts/2    T      0:00 ./mysplit 4\n\
+                  26917 pts/3    T      0:00 ./mysplit 4\n\
+                  (9999999): No such process (not a pid)\n";
+    let expected = "Job [1] (PID) stopped by signal 20\n\
+                    [1] (PID) Stopped ./mysplit 4\n\
+                    STAT COMMAND\n\
+                    T ./mysplit 4\n\
+                    (PID): No such process (not a pid)\n";
+    assert_eq!(normalize(output, "pts/2"), expected);
+}
@@ -0,0 +1,52 @@
+/* 
+ * mycont.c - Another handy routine for testing your tiny shell
+ * 
+ * usage: mycont <n>
+ * Sleeps for <n> seconds and sends SIGTSTP to its process group, like
+ * mystop. A helper process outside of that group sends SIGCONT to the
+ * group <n> seconds later, after which mycont sleeps for <n> more
+ * seconds and exits.
+ *
+ */
+#include <stdio.h>
+#include <unistd.h>
+#include <stdlib.h>
+#include <sys/types.h>
+#include <sys/wait.h>
+#include <signal.h>
+
+int main(int argc, char **argv) 
+{
+    int i, secs;
+    pid_t pid, helper; 
+
+    if (argc != 2) {
+	fprintf(stderr, "Usage: %s <n>\n", argv[0]);
+	exit(0);
+    }
+    secs = atoi(argv[1]);
+    pid = getpid(); 
+
+    if ((helper = fork()) == 0) { /* helper */
+	/* Leave the process group so that the SIGTSTP doesn't stop us too */
+	setpgid(0, 0);
+	for (i=0; i < 2 * secs; i++)
+	    sleep(1);
+	if (kill(-pid, SIGCONT) < 0)
+	    fprintf(stderr, "kill (cont) error");
+	exit(0);
+    }
+
+    for (i=0; i < secs; i++)
+       sleep(1);
+
+    if (kill(-pid, SIGTSTP) < 0)
+       fprintf(stderr, "kill (tstp) error");
+
+    /* We only get here once the helper has continued us */
+    waitpid(helper, NULL, 0);
+    for (i=0; i < secs; i++)
+       sleep(1);
+
+    exit(0);
+}